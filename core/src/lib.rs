@@ -1,14 +1,23 @@
-use std::{
-    any::{Any, TypeId},
-    fmt::Debug,
-};
+use const_panic::ArrayString;
+use std::{any::Any, fmt::Debug, ops::ControlFlow};
 
 pub mod macros {
     pub use zambaga_macros::*;
 }
 
+// for `#[derive(zambaga::Reflect)]`
+pub use zambaga_macros::Reflect;
+
 #[derive(Clone, Copy, Debug)]
 pub struct FieldName(pub &'static str);
+#[derive(Clone, Copy, Debug)]
+pub struct VariantName(pub &'static str);
+// `#[zambaga(key)]` or `#[zambaga(key = value)]` on a field, with string literals unquoted
+#[derive(Clone, Copy, Debug)]
+pub struct FieldAnnotation {
+    pub key: &'static str,
+    pub value: Option<&'static str>,
+}
 #[derive(Clone, Copy)]
 pub struct TypeName {
     in_source: &'static str,
@@ -41,10 +50,16 @@ impl TypeName {
 #[derive(Debug)]
 pub struct TypeError;
 
+/// `MakeDynTrait::DynTrait` of traits reflected without `mode = dyn`. It has no values, so the
+/// `Option<&DynTrait>`s handed out for such traits are always `None`.
+pub enum NotDyn {}
+
 pub trait MakeDynTrait {
     type DynTrait<'a>: ?Sized;
     type IsTrait<'a, T: 'a>: ?Sized;
-    type FieldVisitor;
+    /// The associated consts of the reflected trait, each as `Option<_>` (`None` when the field
+    /// type does not implement the trait). Consts whose type mentions `Self` are left out.
+    type FieldConsts: 'static;
     const TRAIT_NAME: &'static str;
 }
 
@@ -54,13 +69,19 @@ type ExtractorFn<MDT> =
 pub struct ImplExtractor<MDT: MakeDynTrait> {
     pub extractor_fn: ExtractorFn<MDT>,
     pub has_impl: bool,
+    pub consts: MDT::FieldConsts,
 }
 
 impl<MDT: MakeDynTrait> ImplExtractor<MDT> {
-    pub const fn new(extractor_fn: ExtractorFn<MDT>, has_impl: bool) -> Self {
+    pub const fn new(
+        extractor_fn: ExtractorFn<MDT>,
+        has_impl: bool,
+        consts: MDT::FieldConsts,
+    ) -> Self {
         Self {
             extractor_fn,
             has_impl,
+            consts,
         }
     }
 
@@ -82,171 +103,513 @@ impl FieldExtractor {
     }
 }
 
-pub struct Validation;
+#[derive(Clone, Copy)]
+enum ValidationError {
+    Custom(&'static str),
+    NotAStruct,
+    UnnamedFields,
+    MissingField(&'static str),
+    TooFewFields(usize),
+    TooManyFields(usize),
+}
+
+// Built in `const VALIDATION` out of the combinators below. The errors are reported by
+//  `#[z::derive]`, at the derive attribute for the rules about the whole type and at the field
+//  for the fields not implementing the trait.
+#[derive(Clone, Copy)]
+pub struct Validation {
+    // every field has to implement the reflected trait
+    all_fields_impl: bool,
+    // whether they do, `true` unless known otherwise
+    fields_impl: bool,
+    error: Option<ValidationError>,
+    message: Option<&'static str>,
+    // for the fields not implementing the trait
+    fields_message: Option<&'static str>,
+}
 
 impl Validation {
     pub const fn ok() -> Self {
-        Validation
+        Validation {
+            all_fields_impl: false,
+            fields_impl: true,
+            error: None,
+            message: None,
+            fields_message: None,
+        }
     }
     pub const fn all_fields_impl<MDT: MakeDynTrait>(mirror: &Mirror<MDT>) -> Validation {
+        let mut fields_impl = true;
+        let mut i = 0;
+        while i < mirror.field_count() {
+            fields_impl &= mirror.field_impl(i).has_impl;
+            i += 1;
+        }
+        Validation {
+            all_fields_impl: true,
+            fields_impl,
+            ..Validation::ok()
+        }
+    }
+    pub const fn err(message: &'static str) -> Self {
+        Validation::ok().fail(ValidationError::Custom(message))
+    }
+
+    // structs and tuple structs, but no enums
+    pub const fn require_struct<MDT: MakeDynTrait>(mirror: &Mirror<MDT>) -> Validation {
         match mirror.fields_or_variants {
-            FieldsOrVariants::Struct { fields } => {
-                let mut i = 0;
-                while i < fields.len() {
-                    let (field_name, field_type, _, show_impl_extractor) = &fields[i];
-                    i += 1;
-                    if !show_impl_extractor.has_impl {
-                        return Validation::doesnt_implement(
-                            field_name,
-                            field_type,
-                            MDT::TRAIT_NAME,
-                        );
-                    }
-                }
-            }
-            FieldsOrVariants::TupleStruct { fields } => {
-                let mut i = 0;
-                while i < fields.len() {
-                    let (_, _, show_impl_extractor) = &fields[i];
-                    i += 1;
-                    if !show_impl_extractor.has_impl {
-                        return Validation::err();
-                    }
-                }
+            FieldsOrVariants::Enum { .. } => Validation::ok().fail(ValidationError::NotAStruct),
+            _ => Validation::ok(),
+        }
+    }
+    // structs with named fields, or without fields at all
+    pub const fn require_named_fields<MDT: MakeDynTrait>(mirror: &Mirror<MDT>) -> Validation {
+        let mut i = 0;
+        while i < mirror.field_count() {
+            if mirror.field(i).name.is_none() {
+                return Validation::ok().fail(ValidationError::UnnamedFields);
             }
-            FieldsOrVariants::Enum { variants } => {
-                let mut i = 0;
-                while i < variants.len() {
-                    let (_, _, _, show_impl_extractor) = &variants[i];
-                    i += 1;
-                    if !show_impl_extractor.has_impl {
-                        return Validation::err();
-                    }
+            i += 1;
+        }
+        Validation::require_struct(mirror)
+    }
+    // for enums it's enough if one of the variants has the field
+    pub const fn require_field<MDT: MakeDynTrait>(
+        mirror: &Mirror<MDT>,
+        field_name: &'static str,
+    ) -> Validation {
+        let mut i = 0;
+        while i < mirror.field_count() {
+            if let Some(name) = mirror.field(i).name {
+                if str_eq(name.0, field_name) {
+                    return Validation::ok();
                 }
             }
+            i += 1;
         }
-        Validation
+        Validation::ok().fail(ValidationError::MissingField(field_name))
     }
-    pub const fn err() -> Self {
-        panic!("Validation failed")
+    pub const fn min_fields<MDT: MakeDynTrait>(mirror: &Mirror<MDT>, min: usize) -> Validation {
+        if mirror.field_count() < min {
+            Validation::ok().fail(ValidationError::TooFewFields(min))
+        } else {
+            Validation::ok()
+        }
+    }
+    pub const fn max_fields<MDT: MakeDynTrait>(mirror: &Mirror<MDT>, max: usize) -> Validation {
+        if mirror.field_count() > max {
+            Validation::ok().fail(ValidationError::TooManyFields(max))
+        } else {
+            Validation::ok()
+        }
     }
 
-    pub const fn doesnt_implement(
-        field_name: &FieldName,
-        field_type: &TypeName,
-        trait_name: &str,
-    ) -> Validation {
-        const_panic::concat_panic!(const_panic::FmtArg::DISPLAY;
-            "\nField `", field_name.0, "` of type `", field_type.compiletime(), "` does not implement `", trait_name, "`\n"
-        )
+    // the messages stay with the rules they were given for
+    pub const fn and(self, other: Validation) -> Validation {
+        let (error, message) = match self.error {
+            Some(error) => (Some(error), self.message),
+            None => (other.error, other.message),
+        };
+        let fields_message = if self.all_fields_impl {
+            self.fields_message
+        } else {
+            other.fields_message
+        };
+        Validation {
+            all_fields_impl: self.all_fields_impl || other.all_fields_impl,
+            fields_impl: self.fields_impl && other.fields_impl,
+            error,
+            message,
+            fields_message,
+        }
+    }
+    // when neither passes, the errors of `self` are reported
+    pub const fn or(self, other: Validation) -> Validation {
+        if self.is_ok() || !other.is_ok() {
+            self
+        } else {
+            other
+        }
+    }
+    // replaces the default messages of the errors, including the ones of the fields
+    pub const fn with_message(self, message: &'static str) -> Validation {
+        Validation {
+            message: Some(message),
+            fields_message: Some(message),
+            ..self
+        }
     }
-}
 
-pub struct FieldTraverser<MDT: MakeDynTrait> {
-    f: fn(&(), &mut <MDT as MakeDynTrait>::FieldVisitor),
-    expected_type_id: fn() -> TypeId,
-}
+    pub const fn is_ok(&self) -> bool {
+        self.error.is_none() && (!self.all_fields_impl || self.fields_impl)
+    }
 
-impl<MDT> FieldTraverser<MDT>
-where
-    MDT: MakeDynTrait,
-{
-    pub const fn new<T: ?Sized + 'static>(
-        f: fn(&T, &mut <MDT as MakeDynTrait>::FieldVisitor),
-    ) -> Self {
-        Self {
-            f: unsafe {
-                std::mem::transmute::<
-                    fn(&T, &mut <MDT as MakeDynTrait>::FieldVisitor),
-                    fn(&(), &mut <MDT as MakeDynTrait>::FieldVisitor),
-                >(f)
-            },
-            expected_type_id: || TypeId::of::<T>(),
+    const fn fail(self, error: ValidationError) -> Validation {
+        Validation {
+            error: Some(error),
+            ..self
         }
     }
 
-    pub fn accept<T: ?Sized + 'static>(
+    // `#[z::derive]` calls this once per derived type, at the derive attribute
+    pub const fn check<MDT: MakeDynTrait>(&self, mirror: &Mirror<MDT>) {
+        use const_panic::{concat_panic, FmtArg};
+
+        let Some(error) = self.error else {
+            return;
+        };
+        let type_name = mirror.name.compiletime();
+        if let Some(message) = self.message {
+            concat_panic!(FmtArg::DISPLAY;
+                "\nCannot derive `", MDT::TRAIT_NAME, "` for `", type_name, "`: ", message, "\n"
+            )
+        }
+        match error {
+            ValidationError::Custom(message) => concat_panic!(FmtArg::DISPLAY;
+                "\nCannot derive `", MDT::TRAIT_NAME, "` for `", type_name, "`: ", message, "\n"
+            ),
+            ValidationError::NotAStruct => concat_panic!(FmtArg::DISPLAY;
+                "\n`", type_name, "` is an enum, but deriving `", MDT::TRAIT_NAME,
+                "` requires a struct\n"
+            ),
+            ValidationError::UnnamedFields => concat_panic!(FmtArg::DISPLAY;
+                "\n`", type_name, "` has unnamed fields, but deriving `", MDT::TRAIT_NAME,
+                "` requires named fields\n"
+            ),
+            ValidationError::MissingField(field_name) => concat_panic!(FmtArg::DISPLAY;
+                "\n`", type_name, "` has no field `", field_name, "`, which deriving `",
+                MDT::TRAIT_NAME, "` requires\n"
+            ),
+            ValidationError::TooFewFields(min) => concat_panic!(FmtArg::DISPLAY;
+                "\n`", type_name, "` has ", mirror.field_count(), " fields, but deriving `",
+                MDT::TRAIT_NAME, "` requires at least ", min, "\n"
+            ),
+            ValidationError::TooManyFields(max) => concat_panic!(FmtArg::DISPLAY;
+                "\n`", type_name, "` has ", mirror.field_count(), " fields, but deriving `",
+                MDT::TRAIT_NAME, "` allows at most ", max, "\n"
+            ),
+        }
+    }
+
+    // `#[z::derive]` calls this for every field, at the field's span, so that each offending
+    //  field is reported on its own
+    pub const fn check_field<MDT: MakeDynTrait>(&self, mirror: &Mirror<MDT>, index: usize) {
+        let field = mirror.field(index);
+        if !self.all_fields_impl || field.impl_extractor.has_impl {
+            return;
+        }
+        panic!("{}", self.field_message(mirror, index).to_str());
+    }
+
+    const fn field_message<MDT: MakeDynTrait>(
         &self,
-        field_visitor: &mut <MDT as MakeDynTrait>::FieldVisitor,
-        this: &T,
-    ) {
-        if (self.expected_type_id)() != TypeId::of::<T>() {
-            panic!("TypeId mismatch");
+        mirror: &Mirror<MDT>,
+        index: usize,
+    ) -> ArrayString<FIELD_MESSAGE_LEN> {
+        use const_panic::{FmtArg, PanicVal};
+
+        let field = mirror.field(index);
+        // fields of enums are reported as `Variant::field`
+        let (variant_name, separator) = match field.variant {
+            Some(variant_name) => (variant_name.0, "::"),
+            None => ("", ""),
+        };
+        // tuple fields are reported by their index
+        let field_name = match field.name {
+            Some(field_name) => PanicVal::write_str(field_name.0),
+            None => PanicVal::from_usize(field.index, FmtArg::DISPLAY),
+        };
+        let (message_separator, message) = match self.fields_message {
+            Some(message) => (": ", message),
+            None => ("", ""),
+        };
+        match ArrayString::concat_panicvals(&[&[
+            PanicVal::write_str("\nField `"),
+            PanicVal::write_str(variant_name),
+            PanicVal::write_str(separator),
+            field_name,
+            PanicVal::write_str("` of type `"),
+            PanicVal::write_str(field.type_name.compiletime()),
+            PanicVal::write_str("` does not implement `"),
+            PanicVal::write_str(MDT::TRAIT_NAME),
+            PanicVal::write_str("`"),
+            PanicVal::write_str(message_separator),
+            PanicVal::write_str(message),
+            PanicVal::write_str("\n"),
+        ]]) {
+            Some(message) => message,
+            None => ArrayString::new("\nThe field does not implement the trait\n"),
+        }
+    }
+}
+
+// long enough for the type names and the custom messages of `with_message`
+const FIELD_MESSAGE_LEN: usize = 4096;
+
+/// Whether `module_path`, as expanded from `module_path!()`, is `module` relative to the crate root,
+/// e.g. `traits::inner`, or `""` for the root itself.
+pub const fn is_module(module_path: &str, module: &str) -> bool {
+    let bytes = module_path.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b':' && bytes[i + 1] == b':' {
+            return bytes_eq(bytes.split_at(i + 2).1, module.as_bytes());
         }
-        (unsafe {
-            std::mem::transmute::<
-                fn(&(), &mut <MDT as MakeDynTrait>::FieldVisitor),
-                fn(&T, &mut <MDT as MakeDynTrait>::FieldVisitor),
-            >(self.f)
-        })(this, field_visitor);
+        i += 1;
+    }
+    module.is_empty()
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    bytes_eq(a.as_bytes(), b.as_bytes())
+}
+
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// `Continue` for `Ok`, `Break` with the error otherwise, for the `try_visit_implemented` methods of
+/// the visitors calling fallible methods of the fields.
+pub fn break_on_err<E>(result: Result<(), E>) -> ControlFlow<E> {
+    match result {
+        Ok(()) => ControlFlow::Continue(()),
+        Err(error) => ControlFlow::Break(error),
     }
 }
 
 pub struct Mirror<MDT: MakeDynTrait + 'static> {
     pub name: TypeName,
-    pub field_traverser: FieldTraverser<MDT>,
     pub fields_or_variants: FieldsOrVariants<MDT>,
 }
 
+impl<MDT: MakeDynTrait + 'static> Mirror<MDT> {
+    pub const fn field_count(&self) -> usize {
+        self.fields_or_variants.field_count()
+    }
+
+    // usable in const fns, e.g. to fold the `consts` of all fields into an associated const
+    pub const fn field_impl(&self, index: usize) -> &'static ImplExtractor<MDT> {
+        self.field(index).impl_extractor
+    }
+
+    // the fields of all variants of an enum are numbered one after another
+    pub const fn field(&self, index: usize) -> FieldRef<MDT> {
+        self.fields_or_variants.field(index)
+    }
+}
+
+type NamedFieldMeta<MDT> = (
+    FieldName,
+    TypeName,
+    FieldExtractor,
+    ImplExtractor<MDT>,
+    &'static [FieldAnnotation],
+);
+type UnnamedFieldMeta<MDT> = (
+    TypeName,
+    FieldExtractor,
+    ImplExtractor<MDT>,
+    &'static [FieldAnnotation],
+);
+
 pub enum FieldsOrVariants<MDT: MakeDynTrait + 'static> {
     Struct {
-        fields: &'static [(FieldName, TypeName, FieldExtractor, ImplExtractor<MDT>)],
+        fields: &'static [NamedFieldMeta<MDT>],
     },
     TupleStruct {
-        fields: &'static [(TypeName, FieldExtractor, ImplExtractor<MDT>)],
+        fields: &'static [UnnamedFieldMeta<MDT>],
     },
     Enum {
-        variants: &'static [(FieldName, TypeName, FieldExtractor, ImplExtractor<MDT>)],
+        variant_index: fn(&dyn Any) -> Result<usize, TypeError>,
+        // the fields of each variant, either as `Struct` (also for unit variants) or `TupleStruct`
+        variants: &'static [(VariantName, FieldsOrVariants<MDT>)],
     },
 }
 
+impl<MDT: MakeDynTrait + 'static> Clone for FieldsOrVariants<MDT> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<MDT: MakeDynTrait + 'static> Copy for FieldsOrVariants<MDT> {}
+
+impl<MDT: MakeDynTrait + 'static> FieldsOrVariants<MDT> {
+    pub const fn field_count(&self) -> usize {
+        match *self {
+            FieldsOrVariants::Struct { fields } => fields.len(),
+            FieldsOrVariants::TupleStruct { fields } => fields.len(),
+            FieldsOrVariants::Enum { variants, .. } => {
+                let mut count = 0;
+                let mut i = 0;
+                while i < variants.len() {
+                    count += variants[i].1.field_count();
+                    i += 1;
+                }
+                count
+            }
+        }
+    }
+
+    pub const fn field(&self, index: usize) -> FieldRef<MDT> {
+        match *self {
+            FieldsOrVariants::Struct { fields } => {
+                let (name, type_name, extractor, impl_extractor, annotations) = &fields[index];
+                FieldRef {
+                    variant: None,
+                    name: Some(*name),
+                    index,
+                    count: fields.len(),
+                    type_name: *type_name,
+                    extractor,
+                    impl_extractor,
+                    annotations,
+                }
+            }
+            FieldsOrVariants::TupleStruct { fields } => {
+                let (type_name, extractor, impl_extractor, annotations) = &fields[index];
+                FieldRef {
+                    variant: None,
+                    name: None,
+                    index,
+                    count: fields.len(),
+                    type_name: *type_name,
+                    extractor,
+                    impl_extractor,
+                    annotations,
+                }
+            }
+            FieldsOrVariants::Enum { variants, .. } => {
+                let mut index = index;
+                let mut i = 0;
+                loop {
+                    let (variant_name, fields) = &variants[i];
+                    if index < fields.field_count() {
+                        let mut field = fields.field(index);
+                        field.variant = Some(*variant_name);
+                        return field;
+                    }
+                    index -= fields.field_count();
+                    i += 1;
+                }
+            }
+        }
+    }
+}
+
+pub struct FieldRef<MDT: MakeDynTrait + 'static> {
+    pub variant: Option<VariantName>,
+    // `None` for the fields of tuple structs and variants
+    pub name: Option<FieldName>,
+    // the position of the field within its struct or variant
+    pub index: usize,
+    // the number of fields of its struct or variant
+    pub count: usize,
+    pub type_name: TypeName,
+    pub extractor: &'static FieldExtractor,
+    pub impl_extractor: &'static ImplExtractor<MDT>,
+    pub annotations: &'static [FieldAnnotation],
+}
+
+// Handed to the field visitors along with each field.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldContext {
+    // the type the field belongs to
+    pub owner: TypeName,
+    pub variant: Option<VariantName>,
+    pub name: Option<FieldName>,
+    pub type_name: TypeName,
+    // the position of the field within its struct or variant, out of `count`
+    pub index: usize,
+    pub count: usize,
+    pub annotations: &'static [FieldAnnotation],
+}
+
+impl FieldContext {
+    pub const fn new<MDT: MakeDynTrait>(mirror: &Mirror<MDT>, index: usize) -> Self {
+        let field = mirror.field(index);
+        FieldContext {
+            owner: mirror.name,
+            variant: field.variant,
+            name: field.name,
+            type_name: field.type_name,
+            index: field.index,
+            count: field.count,
+            annotations: field.annotations,
+        }
+    }
+
+    pub const fn is_first(&self) -> bool {
+        self.index == 0
+    }
+
+    pub const fn is_last(&self) -> bool {
+        self.index + 1 == self.count
+    }
+
+    pub const fn annotation(&self, key: &str) -> Option<FieldAnnotation> {
+        let mut i = 0;
+        while i < self.annotations.len() {
+            if str_eq(self.annotations[i].key, key) {
+                return Some(self.annotations[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    pub const fn has_annotation(&self, key: &str) -> bool {
+        self.annotation(key).is_some()
+    }
+}
+
 pub trait WithMirror<MDT: MakeDynTrait + 'static>: Sized + 'static {
+    /// The types of the fields as a tuple, in declaration order. Lets derivations compute types from
+    /// the field types, e.g. associated types of the reflected trait.
+    type FieldTypes;
     const MIRROR: Mirror<MDT>;
 
     fn fields(
         &self,
     ) -> impl Iterator<Item = (Option<FieldName>, TypeName, Option<&MDT::DynTrait<'_>>)> {
-        enum OneOfThree<T, U, V> {
-            One(T),
-            Two(U),
-            Three(V),
-        }
-        impl<T, U, V, I> Iterator for OneOfThree<T, U, V>
-        where
-            T: Iterator<Item = I>,
-            U: Iterator<Item = I>,
-            V: Iterator<Item = I>,
-        {
-            type Item = T::Item;
-            fn next(&mut self) -> Option<Self::Item> {
-                match self {
-                    Self::One(x) => x.next(),
-                    Self::Two(x) => x.next(),
-                    Self::Three(x) => x.next(),
-                }
-            }
-        }
+        // only the fields of the variant the value is of
+        let fields_or_variants = match Self::MIRROR.fields_or_variants {
+            FieldsOrVariants::Enum {
+                variant_index,
+                variants,
+            } => variants[variant_index(self).unwrap()].1,
+            fields_or_variants => fields_or_variants,
+        };
+        let (named, unnamed): (&'static [_], &'static [_]) = match fields_or_variants {
+            FieldsOrVariants::Struct { fields } => (fields, &[]),
+            FieldsOrVariants::TupleStruct { fields } => (&[], fields),
+            FieldsOrVariants::Enum { .. } => (&[], &[]),
+        };
 
-        match Self::MIRROR.fields_or_variants {
-            FieldsOrVariants::Struct { fields } => OneOfThree::One(fields.iter().map(
-                |(name, type_name, extractor, show_impl_extractor)| {
-                    let value = (extractor.extractor_fn)(self).unwrap();
-                    let value = (show_impl_extractor.extractor_fn)(value).unwrap();
-                    (Some(*name), *type_name, value)
-                },
-            )),
-            FieldsOrVariants::TupleStruct { fields } => OneOfThree::Two(fields.iter().map(
-                |(type_name, extractor, show_impl_extractor)| {
-                    let value = (extractor.extractor_fn)(self).unwrap();
-                    let value = (show_impl_extractor.extractor_fn)(value).unwrap();
-                    (None, *type_name, value)
-                },
-            )),
-            FieldsOrVariants::Enum { variants } => OneOfThree::Three(variants.iter().map(
-                |(_name, _type_name, _extractor, _show_impl_extractor)| {
-                    unimplemented!("this is wrong");
-                },
-            )),
-        }
+        let named = named
+            .iter()
+            .map(|(name, type_name, extractor, show_impl_extractor, _)| {
+                let value = (extractor.extractor_fn)(self).unwrap();
+                let value = (show_impl_extractor.extractor_fn)(value).unwrap();
+                (Some(*name), *type_name, value)
+            });
+        let unnamed = unnamed
+            .iter()
+            .map(|(type_name, extractor, show_impl_extractor, _)| {
+                let value = (extractor.extractor_fn)(self).unwrap();
+                let value = (show_impl_extractor.extractor_fn)(value).unwrap();
+                (None, *type_name, value)
+            });
+        named.chain(unnamed)
     }
 }
@@ -0,0 +1,6 @@
+// the errors `#[z::derive]` reports at compile time, see `tests/ui`
+#[test]
+fn compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,29 @@
+use zambaga::macros as z;
+
+#[z::reflect]
+pub trait Show {
+    fn show(&self) -> String;
+}
+
+#[z::derivation(Show)]
+pub mod show_derivation {
+    use super::*;
+
+    pub fn show<T: ReflectShow>(_zelf: &T) -> String {
+        String::new()
+    }
+}
+
+impl Show for u8 {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[z::derive(Show, validation = )]
+pub struct Point {
+    #[zambaga(rename = "first")]
+    pub x: u8,
+}
+
+fn main() {}
@@ -0,0 +1,20 @@
+use zambaga::macros as z;
+
+#[z::reflect]
+pub trait Show {
+    fn show(&self) -> String;
+}
+
+#[z::derivation(Show)]
+pub mod show_derivation {
+    use super::*;
+
+    pub fn show<T: ReflectShow>(_zelf: &T) -> String {
+        String::new()
+    }
+}
+
+#[z::derive(Show)]
+pub enum Never {}
+
+fn main() {}
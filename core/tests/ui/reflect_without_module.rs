@@ -0,0 +1,10 @@
+pub mod traits {
+    use zambaga::macros as z;
+
+    #[z::reflect]
+    pub trait Show {
+        fn show(&self) -> String;
+    }
+}
+
+fn main() {}
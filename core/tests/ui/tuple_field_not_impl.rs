@@ -0,0 +1,35 @@
+use zambaga::macros as z;
+
+#[z::reflect]
+pub trait Show {
+    fn show(&self) -> String;
+}
+
+#[z::derivation(Show)]
+pub mod show_derivation {
+    use super::*;
+
+    pub fn show<T: ReflectShow>(_zelf: &T) -> String {
+        String::new()
+    }
+}
+
+impl Show for u8 {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+pub struct Label;
+
+// tuple fields are reported by their index, prefixed by the variant for enums
+#[z::derive(Show)]
+pub struct Labeled(u8, Label);
+
+#[z::derive(Show)]
+pub enum Tagged {
+    Plain(u8),
+    Labeled(u8, Label),
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use zambaga::macros as z;
+
+#[z::reflect]
+pub trait Show {
+    fn show(&self) -> String;
+}
+
+#[z::derivation(Show)]
+pub mod show_derivation {
+    use super::*;
+
+    pub const VALIDATION: zambaga::Validation = zambaga::Validation::ok();
+
+    pub fn show<T: ReflectShow>(_zelf: &T) -> String {
+        String::new()
+    }
+}
+
+fn main() {}
@@ -0,0 +1,32 @@
+use zambaga::macros as z;
+
+#[z::reflect]
+pub trait Show {
+    fn show(&self) -> String;
+}
+
+#[z::derivation(Show)]
+pub mod show_derivation {
+    use super::*;
+
+    pub fn show<T: ReflectShow>(_zelf: &T) -> String {
+        String::new()
+    }
+}
+
+impl Show for u8 {
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+pub struct Label;
+
+// reported as `Variant::field`
+#[z::derive(Show)]
+pub enum Shape {
+    Circle { radius: u8, label: Label },
+    Square { side: u8 },
+}
+
+fn main() {}
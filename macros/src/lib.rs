@@ -1,6 +1,9 @@
+use enumflags2::BitFlags;
+use mode::ReflectionMode;
 use proc_macro::TokenStream as PMTokenStream;
 use proc_macro2::{Ident, Span, TokenStream, TokenTree};
-use quote::{quote, ToTokens};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
 
 mod mode;
 
@@ -9,135 +12,681 @@ pub fn derive(_attr: PMTokenStream, item: PMTokenStream) -> PMTokenStream {
     derive_impl(_attr.into(), item.into()).into()
 }
 
+struct DeriveArgs {
+    trait_path: syn::Path,
+    validation: DeriveValidation,
+    // `crate = path` for when zambaga is only available through another crate
+    zambaga_path: TokenStream,
+}
+
+// `validation = ...` in `#[z::derive(Trait, validation = ...)]`
+enum DeriveValidation {
+    // the `VALIDATION` of the derivation
+    Derivation,
+    // no checks at all, the fields not implementing the trait end up in `visit_any`
+    Unchecked,
+    // `|mirror| expr` replacing the `VALIDATION` of the derivation for this type
+    Replaced(syn::ExprClosure),
+}
+
+impl syn::parse::Parse for DeriveArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let trait_path = input.parse::<syn::Path>().map_err(|e| {
+            syn::Error::new(
+                e.span(),
+                "expected the path of a reflected trait, e.g. `#[z::derive(Show)]` or \
+                `#[z::derive(trait_def::Show)]`",
+            )
+        })?;
+        // the macro of the trait is found at the same path, and macros take no generic arguments
+        if let Some(segment) = trait_path
+            .segments
+            .iter()
+            .find(|segment| !segment.arguments.is_empty())
+        {
+            return Err(syn::Error::new_spanned(
+                &segment.arguments,
+                "reflected traits have no generic parameters",
+            ));
+        }
+        let mut validation = DeriveValidation::Derivation;
+        let mut zambaga_path = quote! { ::zambaga };
+        while input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            if input.parse::<Option<syn::Token![crate]>>()?.is_some() {
+                input.parse::<syn::Token![=]>()?;
+                zambaga_path = input.parse::<syn::Path>()?.into_token_stream();
+                continue;
+            }
+            let property = input.parse::<syn::Ident>()?;
+            if property != "validation" {
+                return Err(syn::Error::new(
+                    property.span(),
+                    "unsupported `derive` property, expected `validation` or `crate`",
+                ));
+            }
+            input.parse::<syn::Token![=]>()?;
+            validation = if input.peek(syn::Token![|]) {
+                let closure = input.parse::<syn::ExprClosure>()?;
+                if closure.inputs.len() != 1 {
+                    return Err(syn::Error::new_spanned(
+                        closure.inputs,
+                        "the validation takes the mirror of the type, e.g. \
+                        `validation = |mirror| zambaga::Validation::require_struct(&mirror)`",
+                    ));
+                }
+                DeriveValidation::Replaced(closure)
+            } else {
+                let value = input.parse::<syn::Ident>()?;
+                if value != "unchecked" {
+                    return Err(syn::Error::new(
+                        value.span(),
+                        "expected `unchecked` or a closure taking the mirror of the type",
+                    ));
+                }
+                DeriveValidation::Unchecked
+            };
+        }
+        Ok(DeriveArgs {
+            trait_path,
+            validation,
+            zambaga_path,
+        })
+    }
+}
+
+enum Adt {
+    Struct(syn::ItemStruct),
+    Enum(syn::ItemEnum),
+}
+
+impl Adt {
+    fn parse(item: TokenStream, macro_name: &str) -> syn::Result<Self> {
+        match syn::parse2::<syn::Item>(item)? {
+            syn::Item::Struct(item) => Ok(Adt::Struct(item)),
+            syn::Item::Enum(item) => Ok(Adt::Enum(item)),
+            other => Err(syn::Error::new_spanned(
+                other,
+                format!("{macro_name} can only be used on structs and enums"),
+            )),
+        }
+    }
+
+    fn attrs(&self) -> &[syn::Attribute] {
+        match self {
+            Adt::Struct(item) => &item.attrs,
+            Adt::Enum(item) => &item.attrs,
+        }
+    }
+}
+
 fn derive_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let trait_name = attr.into_iter().next().unwrap();
-    enum Adt {
-        Struct(syn::ItemStruct),
-        Enum(syn::ItemEnum),
+    let mut adt = match Adt::parse(item.clone(), "`#[z::derive]`") {
+        Ok(adt) => adt,
+        Err(e) => {
+            let error = e.to_compile_error();
+            return quote! { #item #error };
+        }
+    };
+    let args = syn::parse2::<DeriveArgs>(attr);
+    let annotations_derive = {
+        let zambaga_path = match &args {
+            Ok(args) => args.zambaga_path.clone(),
+            Err(_) => quote! { ::zambaga },
+        };
+        quote! { #zambaga_path::macros::__ZambagaAnnotations }
+    };
+    // the item is emitted even when it can't be derived for, so that its uses don't error too
+    let impls = match args.and_then(|args| derive_trait(args, &adt)) {
+        Ok(impls) => impls,
+        Err(e) => e.to_compile_error(),
+    };
+
+    // `#[zambaga(...)]` on the fields is kept for the derives that come next, so it needs a
+    //  derive declaring it, whatever else the item derives
+    let (attrs, fields) = match &mut adt {
+        Adt::Struct(item) => (&mut item.attrs, vec![&item.fields]),
+        Adt::Enum(item) => (
+            &mut item.attrs,
+            item.variants.iter().map(|variant| &variant.fields).collect(),
+        ),
+    };
+    let is_annotated = fields
+        .iter()
+        .flat_map(|fields| fields.iter())
+        .any(|field| field.attrs.iter().any(|attr| attr.path().is_ident("zambaga")));
+    let is_declared = attrs.iter().any(|attr| {
+        attr.path().is_ident("derive")
+            && attr
+                .parse_args_with(
+                    syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated,
+                )
+                .is_ok_and(|paths| {
+                    paths
+                        .iter()
+                        .any(|path| path.segments.last().unwrap().ident == "__ZambagaAnnotations")
+                })
+    });
+    if is_annotated && !is_declared {
+        attrs.push(syn::parse_quote! { #[derive(#annotations_derive)] });
     }
+    let item = match adt {
+        Adt::Struct(item) => item.into_token_stream(),
+        Adt::Enum(item) => item.into_token_stream(),
+    };
 
-    let adt: Adt = if let Ok(item) = syn::parse2::<syn::ItemStruct>(item.clone()) {
-        Adt::Struct(item)
-    } else if let Ok(item) = syn::parse2::<syn::ItemEnum>(item.clone()) {
-        Adt::Enum(item)
-    } else {
-        panic!("Expected struct or enum");
+    let res = quote! {
+        #item
+        #impls
     };
 
-    let item = item.into_iter().collect::<Vec<_>>();
-    let name = item[1].clone();
+    // println!("{}", res);
+
+    res
+}
+
+// The implementations `#[z::derive]` adds next to the item, for one trait.
+fn derive_trait(args: DeriveArgs, adt: &Adt) -> syn::Result<TokenStream> {
+    let DeriveArgs {
+        trait_path,
+        validation,
+        zambaga_path,
+    } = args;
+
+    let generics = match adt {
+        Adt::Struct(item) => &item.generics,
+        Adt::Enum(item) => &item.generics,
+    };
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            generics,
+            "generic types are not supported by the zambaga derives",
+        ));
+    }
+    // there would be no values to visit, nor any to build
+    if let Adt::Enum(item) = adt {
+        if item.variants.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &item.ident,
+                "enums without variants are not supported by the zambaga derives",
+            ));
+        }
+    }
+
+    let name = match adt {
+        Adt::Struct(item) => item.ident.clone(),
+        Adt::Enum(item) => item.ident.clone(),
+    };
     let name_lit = name.to_string();
 
     let trait_macro_invocation = quote! {
-        #trait_name!(#name);
-    };
-
-    let with_mirror_impl = match adt {
-        Adt::Struct(item) => {
-            let field_acceptors = item.fields.iter().enumerate().map(|(i, field)| {
-                let field_accessor = field
-                    .ident
-                    .as_ref()
-                    .map(|x| x.to_token_stream())
-                    .unwrap_or_else(|| syn::Index::from(i).to_token_stream());
-                let field_name_value = match &field.ident {
-                    Some(ident) => {
-                        let ident = ident.to_string();
-                        quote! { Some(FieldName(#ident)) }
-                    }
-                    None => quote! { None },
-                };
-                let ty = &field.ty;
-                let ty_lit = ty.to_token_stream().to_string();
+        #trait_path!(#name);
+    };
+
+    struct DeriveField<'a> {
+        member: syn::Member,
+        // the position among the fields of all variants, as in the mirror
+        index: usize,
+        annotations: TokenStream,
+        ty: &'a syn::Type,
+        ty_lit: String,
+        // the field is bound to this name when the value gets destructured
+        binding: Ident,
+        span: Span,
+    }
+    fn derive_fields<'a>(
+        fields: &'a syn::Fields,
+        offset: usize,
+        zambaga_path: &TokenStream,
+    ) -> syn::Result<Vec<DeriveField<'a>>> {
+        fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                Ok(DeriveField {
+                    member: match &field.ident {
+                        Some(ident) => syn::Member::Named(ident.clone()),
+                        None => syn::Member::Unnamed(syn::Index::from(i)),
+                    },
+                    index: offset + i,
+                    annotations: field_annotations(&field.attrs, zambaga_path)?,
+                    ty: &field.ty,
+                    ty_lit: field.ty.to_token_stream().to_string(),
+                    binding: format_ident!("__zambaga_field{}", i),
+                    span: field
+                        .ident
+                        .as_ref()
+                        .map(|ident| ident.span())
+                        .unwrap_or_else(|| field.ty.span()),
+                })
+            })
+            .collect()
+    }
+
+    // the struct itself or each of the variants of the enum, as the path used in patterns and
+    //  struct expressions, the variant name and the fields
+    let shapes = match adt {
+        Adt::Struct(item) => vec![(quote! { #name }, None, &item.fields)],
+        Adt::Enum(item) => item
+            .variants
+            .iter()
+            .map(|variant| {
+                let variant_name = &variant.ident;
+                (
+                    quote! { #name::#variant_name },
+                    Some(variant_name),
+                    &variant.fields,
+                )
+            })
+            .collect(),
+    };
+    let mut offset = 0;
+    let mut errors = Vec::<syn::Error>::new();
+    let shapes = shapes
+        .into_iter()
+        .map(|(path, variant, fields)| {
+            let derive_fields = derive_fields(fields, offset, &zambaga_path).unwrap_or_else(|e| {
+                errors.push(e);
+                vec![]
+            });
+            offset += fields.len();
+            (path, variant, fields, derive_fields)
+        })
+        .collect::<Vec<_>>();
+    if let Some(error) = errors.into_iter().reduce(|mut error, e| {
+        error.combine(e);
+        error
+    }) {
+        return Err(error);
+    }
+    let all_fields = || shapes.iter().flat_map(|(_, _, _, fields)| fields);
+    // evaluated at compile time, out of the mirror
+    let field_context = |field: &DeriveField| {
+        let index = field.index;
+        quote! {
+            const {
+                #zambaga_path::FieldContext::new(
+                    &<#name as #zambaga_path::WithMirror<#trait_path!(@MDT)>>::MIRROR,
+                    #index,
+                )
+            }
+        }
+    };
+
+    // `Path { member: binding, .. }` patterns and `Path { member: value }` expressions work the
+    //  same for named, tuple and unit structs and variants
+    let make_field_visits = |accept: TokenStream, then: TokenStream| {
+        let arms = shapes.iter().map(|(path, _, _, fields)| {
+            let members = fields.iter().map(|field| &field.member);
+            let bindings = fields.iter().map(|field| &field.binding);
+            let acceptors = fields.iter().map(|field| {
+                let DeriveField { ty, binding, .. } = field;
+                let context = field_context(field);
                 quote! {
-                    <#trait_name!(@MDT) as MakeDynTrait>::IsTrait::<'_, #ty>
-                        ::VISITOR_ACCEPTOR.accept(
-                            &this_value.#field_accessor,
-                            #field_name_value,
-                            TypeName::from_source::<#ty>(#ty_lit),
-                            visitor,
-                        );
+                    <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>
+                        ::VISITOR_ACCEPTOR.#accept(#binding, #context, visitor)#then;
                 }
             });
+            quote! {
+                #path { #(#members: #bindings),* } => {
+                    #(#acceptors)*
+                }
+            }
+        });
+        quote! {
+            match this_value {
+                #(#arms)*
+            }
+        }
+    };
+    let field_visits = make_field_visits(quote! { accept }, quote! { ? });
+    let field_async_visits = make_field_visits(quote! { accept_async }, quote! { .await });
 
-            let field_metas = item.fields.iter().enumerate().map(|(i, field)| {
-                let ty = &field.ty;
-                let ty_lit = ty.to_token_stream().to_string();
-                let field_name_lit = field.ident.as_ref().map(|x| x.to_string()).unwrap_or_else(|| i.to_string());
-                let field_accessor = field
-                    .ident
-                    .as_ref()
-                    .map(|x| x.to_token_stream())
-                    .unwrap_or_else(|| syn::Index::from(i).to_token_stream());
+    // the fields of two values of the same struct or variant, side by side
+    let field_pair_visits = {
+        let arms = shapes.iter().map(|(path, _, _, fields)| {
+            let members = fields.iter().map(|field| &field.member).collect::<Vec<_>>();
+            let bindings = fields.iter().map(|field| &field.binding);
+            let other_bindings = fields
+                .iter()
+                .map(|field| format_ident!("{}_other", field.binding))
+                .collect::<Vec<_>>();
+            let acceptors = fields.iter().zip(&other_bindings).map(|(field, other_binding)| {
+                let DeriveField { ty, binding, .. } = field;
+                let context = field_context(field);
+                quote! {
+                    <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>
+                        ::VISITOR_ACCEPTOR.accept_pair(#binding, #other_binding, #context, visitor);
+                }
+            });
+            quote! {
+                (
+                    #path { #(#members: #bindings),* },
+                    #path { #(#members: #other_bindings),* },
+                ) => {
+                    #(#acceptors)*
+                }
+            }
+        });
+        let different_variants = (shapes.len() > 1).then(|| {
+            let variant_names = shapes.iter().map(|(path, variant, _, _)| {
+                let variant_name_lit = variant.map(|variant| variant.to_string());
+                quote! { #path { .. } => #zambaga_path::VariantName(#variant_name_lit), }
+            });
+            quote! {
+                (this_value, other_value) => {
+                    fn __zambaga_variant_name(value: &#name) -> #zambaga_path::VariantName {
+                        match value {
+                            #(#variant_names)*
+                        }
+                    }
+                    visitor.visit_different_variants(
+                        __zambaga_variant_name(this_value),
+                        __zambaga_variant_name(other_value),
+                    );
+                }
+            }
+        });
+        quote! {
+            match (this_value, other_value) {
+                #(#arms)*
+                #different_variants
+            }
+        }
+    };
 
+    let field_type_acceptors = all_fields().map(|field| {
+        let ty = field.ty;
+        let context = field_context(field);
+        quote! {
+            <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>
+                ::VISITOR_ACCEPTOR.accept_type::<#ty, _>(#context, visitor);
+        }
+    });
+
+    // enums are built as their first variant
+    let built_value = {
+        let (path, _, _, fields) = &shapes[0];
+        let field_builders = fields.iter().map(|field| {
+            let DeriveField { member, ty, .. } = field;
+            let context = field_context(field);
+            quote! {
+                #member: <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>
+                    ::VISITOR_ACCEPTOR.build::<#ty, _>(#context, builder)?
+            }
+        });
+        quote! { ::std::result::Result::Ok(#path { #(#field_builders),* }) }
+    };
+
+    // the fields of the value, mapped into another value of the same struct or variant
+    let mapped_value = {
+        let arms = shapes.iter().map(|(path, _, _, fields)| {
+            let members = fields.iter().map(|field| &field.member).collect::<Vec<_>>();
+            let bindings = fields.iter().map(|field| &field.binding);
+            let field_mappers = fields.iter().map(|field| {
+                let DeriveField { ty, binding, .. } = field;
+                let context = field_context(field);
                 quote! {
-                    (
-                        FieldName(#field_name_lit),
-                        TypeName::from_source::<#ty>(#ty_lit),
-                        FieldExtractor::new({
-                            use ::std::any::Any;
-                            fn __zambaga_extractor(this_value: &dyn Any) -> Result<&dyn Any, TypeError> {
-                                let value = this_value.downcast_ref::<#name>().ok_or(TypeError)?;
-                                Ok(&value.#field_accessor)
-                            }
-                            __zambaga_extractor
-                        }),
-                        ImplExtractor::<#trait_name!(@MDT)> {
-                            extractor_fn: {
-                                use ::std::any::Any;
-                                fn __zambaga_extractor(
-                                    field_value: &dyn Any,
-                                ) -> Result<Option<&<#trait_name!(@MDT) as MakeDynTrait>::DynTrait<'_>>, TypeError> {
-                                    let value = field_value.downcast_ref::<#ty>().ok_or(TypeError)?;
-                                    Ok(<#trait_name!(@MDT) as MakeDynTrait>::IsTrait::new(value).as_dyn())
-                                }
-
-                                __zambaga_extractor
-                            },
-                            has_impl: {
-                                <#trait_name!(@MDT) as MakeDynTrait>::IsTrait::<'_, #ty>::DOES_IMPLEMENT
-                            },
-                        },
-                    )
+                    <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>
+                        ::VISITOR_ACCEPTOR.map(#binding, #context, mapper)?
                 }
             });
+            quote! {
+                #path { #(#members: #bindings),* } => #path { #(#members: #field_mappers),* },
+            }
+        });
+        quote! {
+            ::std::result::Result::Ok(match this_value {
+                #(#arms)*
+            })
+        }
+    };
 
+    let make_field_meta = |path: &TokenStream, field: &DeriveField| {
+        let DeriveField {
+            member,
+            annotations,
+            ty,
+            ty_lit,
+            ..
+        } = field;
+        let type_name = quote! { #zambaga_path::TypeName::from_source::<#ty>(#ty_lit) };
+        let field_extractor = quote! {
+            #zambaga_path::FieldExtractor::new({
+                use ::std::any::Any;
+                fn __zambaga_extractor(this_value: &dyn ::std::any::Any) -> ::std::result::Result<&dyn ::std::any::Any, #zambaga_path::TypeError> {
+                    let value = this_value.downcast_ref::<#name>().ok_or(#zambaga_path::TypeError)?;
+                    #[allow(unreachable_patterns)]
+                    match value {
+                        #path { #member: field, .. } => ::std::result::Result::Ok(field),
+                        _ => ::std::result::Result::Err(#zambaga_path::TypeError),
+                    }
+                }
+                __zambaga_extractor
+            })
+        };
+        let impl_extractor = quote! {
+            #zambaga_path::ImplExtractor::<#trait_path!(@MDT)> {
+                extractor_fn: {
+                    use ::std::any::Any;
+                    fn __zambaga_extractor(
+                        field_value: &dyn ::std::any::Any,
+                    ) -> ::std::result::Result<::std::option::Option<&<#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::DynTrait<'_>>, #zambaga_path::TypeError> {
+                        let value = field_value.downcast_ref::<#ty>().ok_or(#zambaga_path::TypeError)?;
+                        ::std::result::Result::Ok(<#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::new(value).as_dyn())
+                    }
+
+                    __zambaga_extractor
+                },
+                has_impl: {
+                    <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>::DOES_IMPLEMENT
+                },
+                consts: <#trait_path!(@MDT) as #zambaga_path::MakeDynTrait>::IsTrait::<'_, #ty>::FIELD_CONSTS,
+            }
+        };
+        match member {
+            syn::Member::Named(ident) => {
+                let field_name_lit = ident.to_string();
+                quote! {
+                    (#zambaga_path::FieldName(#field_name_lit), #type_name, #field_extractor, #impl_extractor, #annotations)
+                }
+            }
+            syn::Member::Unnamed(_) => quote! {
+                (#type_name, #field_extractor, #impl_extractor, #annotations)
+            },
+        }
+    };
+    let make_shape_meta =
+        |path: &TokenStream, fields: &syn::Fields, derive_fields: &[DeriveField]| {
+            let field_metas = derive_fields
+                .iter()
+                .map(|field| make_field_meta(path, field));
+            match fields {
+                syn::Fields::Unnamed(_) => quote! {
+                    #zambaga_path::FieldsOrVariants::TupleStruct { fields: &[#(#field_metas),*] }
+                },
+                syn::Fields::Named(_) | syn::Fields::Unit => quote! {
+                    #zambaga_path::FieldsOrVariants::Struct { fields: &[#(#field_metas),*] }
+                },
+            }
+        };
+    let fields_or_variants = match adt {
+        Adt::Struct(_) => {
+            let (path, _, fields, derive_fields) = &shapes[0];
+            make_shape_meta(path, fields, derive_fields)
+        }
+        Adt::Enum(_) => {
+            let variant_indices = shapes.iter().enumerate().map(|(i, (path, _, _, _))| {
+                quote! { #path { .. } => #i, }
+            });
+            let variant_metas = shapes.iter().map(|(path, variant, fields, derive_fields)| {
+                let variant_name_lit = variant.map(|variant| variant.to_string());
+                let shape_meta = make_shape_meta(path, fields, derive_fields);
+                quote! { (#zambaga_path::VariantName(#variant_name_lit), #shape_meta) }
+            });
             quote! {
-                impl WithMirror<#trait_name!(@MDT)> for #name {
-                    const MIRROR: Mirror<#trait_name!(@MDT)> = {
-                        Mirror {
-                            name: TypeName::from_source::<#name>(#name_lit),
-                            field_traverser: {
-                                fn __zambaga_visitor(this_value: &#name, visitor: &mut <#trait_name!(@MDT) as MakeDynTrait>::FieldVisitor) {
-                                    #(#field_acceptors)*
-                                }
-                                FieldTraverser::new(__zambaga_visitor)
-                            },
-                            fields_or_variants: FieldsOrVariants::Struct {
-                                fields: &[
-                                    #(#field_metas,)*
-                                ],
-                            },
+                #zambaga_path::FieldsOrVariants::Enum {
+                    variant_index: {
+                        use ::std::any::Any;
+                        fn __zambaga_variant_index(this_value: &dyn ::std::any::Any) -> ::std::result::Result<usize, #zambaga_path::TypeError> {
+                            let value = this_value.downcast_ref::<#name>().ok_or(#zambaga_path::TypeError)?;
+                            ::std::result::Result::Ok(match *value {
+                                #(#variant_indices)*
+                            })
                         }
-                    };
+                        __zambaga_variant_index
+                    },
+                    variants: &[#(#variant_metas),*],
                 }
             }
         }
-        Adt::Enum(_item) => todo!(),
     };
 
-    let res = quote! {
-        #(#item)*
+    let field_types = all_fields().map(|field| field.ty);
+
+    let mirror = quote! { <#name as #zambaga_path::WithMirror<#trait_path!(@MDT)>>::MIRROR };
+    let validation = match validation {
+        DeriveValidation::Derivation => Some(quote! { #trait_path!(@validation #name) }),
+        DeriveValidation::Unchecked => None,
+        DeriveValidation::Replaced(closure) => {
+            let input = &closure.inputs[0];
+            let body = &closure.body;
+            Some(quote_spanned! {closure.span()=> {
+                let #input = #mirror;
+                let validation: #zambaga_path::Validation = #body;
+                validation
+            }})
+        }
+    };
+
+    let checks = validation.map(|validation| {
+        // the rules about the whole type are reported at the derive attribute. The errors point at
+        //  the called function, so its path can't keep the span of a `crate = path` argument.
+        let type_check_zambaga_path = respan(zambaga_path.clone(), trait_path.span());
+        let type_check = quote_spanned! {trait_path.span()=>
+            const _: () = #type_check_zambaga_path::Validation::check(&#validation, &#mirror);
+        };
+
+        // one check per field, so that each offending field gets its own error pointing at it
+        let field_checks = all_fields().enumerate().map(|(i, field)| {
+            let field_check_zambaga_path = respan(zambaga_path.clone(), field.span);
+            quote_spanned! {field.span=>
+                const _: () = #field_check_zambaga_path::Validation::check_field(&#validation, &#mirror, #i);
+            }
+        });
+
+        quote! {
+            #type_check
+            #(#field_checks)*
+        }
+    });
+
+    let with_mirror_impl = quote! {
+        impl #zambaga_path::WithMirror<#trait_path!(@MDT)> for #name {
+            type FieldTypes = (#(#field_types,)*);
+            const MIRROR: #zambaga_path::Mirror<#trait_path!(@MDT)> = {
+                #zambaga_path::Mirror {
+                    name: #zambaga_path::TypeName::from_source::<#name>(#name_lit),
+                    fields_or_variants: #fields_or_variants,
+                }
+            };
+        }
+
+        #checks
 
-        #trait_name! { @uses;
+        #trait_path!(@reflect #name; |this_value, visitor| {
+            #field_visits
+        }; |this_value, other_value, visitor| {
+            #field_pair_visits
+        }; async |this_value, visitor| {
+            #field_async_visits
+        }; |visitor| {
+            #(#field_type_acceptors)*
+        }; |builder| {
+            #built_value
+        }; |this_value, mapper| {
+            #mapped_value
+        });
+    };
+
+    Ok(quote! {
+        #trait_path! { @uses;
             #trait_macro_invocation
             #with_mirror_impl
         }
+    })
+}
+
+// Declares `#[zambaga(...)]` on the fields of the items using `#[z::derive]`.
+#[doc(hidden)]
+#[proc_macro_derive(__ZambagaAnnotations, attributes(zambaga))]
+pub fn derive_annotations(_item: PMTokenStream) -> PMTokenStream {
+    PMTokenStream::new()
+}
+
+// `#[derive(zambaga::Reflect)]` with `#[zambaga(derive(Trait, Other(validation = ...)))]`, the
+//  same as `#[z::derive(Trait)]` and `#[z::derive(Other, validation = ...)]` but leaving the item
+//  as it is.
+#[proc_macro_derive(Reflect, attributes(zambaga))]
+pub fn derive_reflect(item: PMTokenStream) -> PMTokenStream {
+    derive_reflect_impl(item.into()).into()
+}
+
+fn derive_reflect_impl(item: TokenStream) -> TokenStream {
+    let adt = match Adt::parse(item, "`#[derive(zambaga::Reflect)]`") {
+        Ok(adt) => adt,
+        Err(e) => return e.to_compile_error(),
     };
 
-    // println!("{}", res);
+    // the traits with the arguments they would get in `#[z::derive(...)]`
+    let mut derives = Vec::<(syn::Path, Option<TokenStream>)>::new();
+    let mut zambaga_path = None;
+    for attr in adt
+        .attrs()
+        .iter()
+        .filter(|attr| attr.path().is_ident("zambaga"))
+    {
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("derive") {
+                meta.parse_nested_meta(|derive| {
+                    let args = if derive.input.peek(syn::token::Paren) {
+                        let content;
+                        syn::parenthesized!(content in derive.input);
+                        Some(content.parse::<TokenStream>()?)
+                    } else {
+                        None
+                    };
+                    derives.push((derive.path, args));
+                    Ok(())
+                })
+            } else if meta.path.is_ident("crate") {
+                zambaga_path = Some(meta.value()?.parse::<syn::Path>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported `zambaga` property, expected `derive` or `crate`"))
+            }
+        });
+        if let Err(e) = parsed {
+            return e.to_compile_error();
+        }
+    }
+    if derives.is_empty() {
+        return syn::Error::new(
+            Span::call_site(),
+            "expected the traits to derive, e.g. `#[zambaga(derive(Show))]`",
+        )
+        .to_compile_error();
+    }
 
-    res
+    let zambaga_path = zambaga_path.map(|path| quote! { , crate = #path });
+    derives
+        .into_iter()
+        .map(|(trait_path, args)| {
+            let args = args.map(|args| quote! { , #args });
+            syn::parse2(quote! { #trait_path #args #zambaga_path })
+                .and_then(|args| derive_trait(args, &adt))
+                .unwrap_or_else(|e| e.to_compile_error())
+        })
+        .collect()
 }
 
 #[proc_macro_attribute]
@@ -151,219 +700,1134 @@ macro_rules! make_ident {
     };
 }
 
-fn reflect_impl(_attributes: TokenStream, item: TokenStream) -> TokenStream {
-    let input: syn::ItemTrait = syn::parse2(item).unwrap();
-    let trait_name = input.ident.clone();
-    let trait_name_str_literal = trait_name.to_string();
-    let trait_name_snake_case = heck::AsSnakeCase(trait_name.to_string());
+fn reflect_impl(attributes: TokenStream, item: TokenStream) -> TokenStream {
+    let mut modes = BitFlags::<ReflectionMode>::all();
+    // the macros of the trait reach the items of the trait from other crates through `$crate`,
+    //  which needs the module the trait is in
+    let mut module = Vec::<syn::Ident>::new();
+    let mut zambaga_path = quote! { ::zambaga };
+    let attribute_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("mode") {
+            modes = ReflectionMode::parse_flags(meta.value()?)?;
+            Ok(())
+        } else if meta.path.is_ident("module") {
+            let path = meta.value()?.parse::<syn::Path>()?;
+            let mut segments = path.segments.into_iter().map(|segment| segment.ident);
+            match segments.next() {
+                Some(ident) if ident == "crate" && path.leading_colon.is_none() => {}
+                _ => {
+                    return Err(meta.error(
+                        "expected the path of the module from the crate root, e.g. \
+                        `module = crate::traits`",
+                    ))
+                }
+            }
+            module = segments.collect();
+            Ok(())
+        } else if meta.path.is_ident("crate") {
+            zambaga_path = meta.value()?.parse::<syn::Path>()?.into_token_stream();
+            Ok(())
+        } else {
+            Err(meta.error("unsupported `reflect` property, expected `mode`, `module` or `crate`"))
+        }
+    });
+    if let Err(e) = syn::parse::Parser::parse2(attribute_parser, attributes) {
+        let error = e.to_compile_error();
+        return quote! { #item #error };
+    }
+    let recursive_mode = modes.contains(ReflectionMode::Recursive);
+    let dyn_mode = modes.contains(ReflectionMode::Dyn);
+
+    let input = match syn::parse2::<syn::Item>(item.clone()) {
+        Ok(syn::Item::Trait(input)) => input,
+        Ok(other) => {
+            let error =
+                syn::Error::new_spanned(other, "`#[z::reflect]` can only be used on traits")
+                    .to_compile_error();
+            return quote! { #item #error };
+        }
+        Err(e) => {
+            let error = e.to_compile_error();
+            return quote! { #item #error };
+        }
+    };
+    if let Err(e) = check_reflected_trait(&input) {
+        let error = e.to_compile_error();
+        return quote! { #item #error };
+    }
+    let has_async_methods = input.items.iter().any(|item| match item {
+        syn::TraitItem::Fn(item_fn) => is_async(&item_fn.sig),
+        _ => false,
+    });
+    // async methods need visitors which can be awaited
+    let async_mode = recursive_mode && has_async_methods;
+    // the futures of the visitors have to be `Send` for those of the trait to be
+    let send_futures = input.items.iter().any(|item| match item {
+        syn::TraitItem::Fn(item_fn) => returns_send_future(&item_fn.sig),
+        _ => false,
+    });
+    let send_bound = send_futures.then(|| quote! { + ::std::marker::Send });
+    let sync_bound = send_futures.then(|| quote! { + ::std::marker::Sync });
+    // the default consts and methods are forwarded like the rest, see `default_items_trait`, but
+    //  the default types, which can't be overridden on stable anyway, are left to the trait
+    let is_default_item = |item: &syn::TraitItem| match item {
+        syn::TraitItem::Const(item) => item.default.is_some(),
+        syn::TraitItem::Fn(item) => item.default.is_some(),
+        _ => false,
+    };
+    let is_default_type = |item: &syn::TraitItem| match item {
+        syn::TraitItem::Type(item) => item.default.is_some(),
+        _ => false,
+    };
+    let has_default_items = input.items.iter().any(is_default_item);
+    let trait_name = input.ident.clone();
+    let trait_name_str_literal = trait_name.to_string();
+    let trait_name_snake_case = heck::AsSnakeCase(trait_name.to_string());
+
+    let visit_field_trait_name = make_ident!("Visit{}Field", trait_name);
+    let try_visit_field_trait_name = make_ident!("TryVisit{}Field", trait_name);
+    let fold_field_trait_name = make_ident!("Fold{}Field", trait_name);
+    let visit_field_pair_trait_name = make_ident!("Visit{}FieldPair", trait_name);
+    let visit_field_type_trait_name = make_ident!("Visit{}FieldType", trait_name);
+    let visit_field_async_trait_name = make_ident!("Visit{}FieldAsync", trait_name);
+    let build_field_trait_name = make_ident!("Build{}Field", trait_name);
+    let map_field_trait_name = make_ident!("Map{}Field", trait_name);
+    let reflect_trait_name = make_ident!("Reflect{}", trait_name);
+    let context_struct_name = make_ident!("{}Context", trait_name);
+    let derive_trait_name = make_ident!("Derive{}", trait_name);
+    let mdt_struct_name = make_ident!("MDT{}", trait_name);
+    let is_trait_struct_name = make_ident!("Is{}", trait_name);
+    let field_consts_struct_name = make_ident!("{}FieldConsts", trait_name);
+    // the helpers are named after the trait too, as the items copied from the trait see them
+    let default_case_trait_name = make_ident!("{}DefaultCase", trait_name);
+    let any_visitor_acceptor_name = make_ident!("Any{}VisitorAcceptor", trait_name);
+    let implemented_visitor_acceptor_name = make_ident!("Implemented{}VisitorAcceptor", trait_name);
+    let field_folder_struct_name = make_ident!("{}FieldFolder", trait_name);
+    let impl_module_name = make_ident!("__zambaga_{}_impl", trait_name_snake_case);
+    let impl_trait_macro_name = make_ident!("{}Macro", trait_name);
+    let default_items_trait_name = make_ident!("{}DefaultItems", trait_name);
+    // `#[macro_export]` puts the macro at the root of the crate, so its name has to tell apart the
+    //  traits of the same name in different modules
+    let exported_macro_name = make_ident!(
+        "__zambaga_{}_macro",
+        module
+            .iter()
+            .map(|segment| segment.to_string())
+            .chain([trait_name_snake_case.to_string()])
+            .collect::<Vec<_>>()
+            .join("__")
+    );
+    // for the macro arms, which expand in the crates deriving the trait
+    let trait_module_path = quote! { $crate #(::#module)* };
+    let impl_module_path = quote! { #trait_module_path ::#impl_module_name };
+    let trait_path = quote! { #trait_module_path ::#trait_name };
+
+    let module_str_literal = module
+        .iter()
+        .map(|segment| segment.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    let module_message = format!(
+        "the crates deriving `{}` look for it in `{}`, pass the module it is in to \
+         `#[z::reflect]`, e.g. `#[z::reflect(module = crate::traits)]`",
+        trait_name,
+        ["crate".to_string()]
+            .into_iter()
+            .chain(module.iter().map(|segment| segment.to_string()))
+            .collect::<Vec<_>>()
+            .join("::"),
+    );
+
+    let dyn_trait = if dyn_mode {
+        quote! { dyn #trait_name + 'a }
+    } else {
+        quote! { #zambaga_path::NotDyn }
+    };
+    let mdt = quote! {
+        pub struct #mdt_struct_name;
+
+        impl #zambaga_path::MakeDynTrait for #mdt_struct_name {
+            type DynTrait<'a> = #dyn_trait;
+            type IsTrait<'a, T: 'a> = #is_trait_struct_name<'a, T>;
+            type FieldConsts = #field_consts_struct_name;
+            const TRAIT_NAME: &'static str = #trait_name_str_literal;
+        }
+    };
+
+    let default_case_visitor_acceptor = recursive_mode.then(|| {
+        quote! {
+            const VISITOR_ACCEPTOR: #any_visitor_acceptor_name = #any_visitor_acceptor_name;
+        }
+    });
+    let implemented_visitor_acceptor = recursive_mode.then(|| {
+        quote! {
+            pub const VISITOR_ACCEPTOR: #implemented_visitor_acceptor_name = #implemented_visitor_acceptor_name;
+        }
+    });
+    let implemented_as_dyn = dyn_mode.then(|| {
+        quote! {
+            pub fn as_dyn(&self) -> ::std::option::Option<&'a dyn #trait_name> {
+                ::std::option::Option::Some(self.0 as &dyn #trait_name)
+            }
+        }
+    });
+    let any_accept_async = async_mode.then(|| {
+        quote! {
+            pub async fn accept_async<Visitor: #visit_field_async_trait_name>(
+                self,
+                field_value: &(dyn ::std::any::Any #sync_bound),
+                field: #zambaga_path::FieldContext,
+                visitor: &mut Visitor,
+            ) {
+                visitor.visit_any(field_value, field).await;
+            }
+        }
+    });
+    let implemented_accept_async = async_mode.then(|| {
+        quote! {
+            pub async fn accept_async<T: #trait_name #sync_bound, Visitor: #visit_field_async_trait_name>(
+                self,
+                field_value: &T,
+                field: #zambaga_path::FieldContext,
+                visitor: &mut Visitor,
+            ) {
+                visitor.visit_implemented(field_value, field).await;
+            }
+        }
+    });
+    let visitor_acceptors = recursive_mode.then(|| {
+        quote! {
+            pub struct #any_visitor_acceptor_name;
+
+            impl #any_visitor_acceptor_name {
+                pub fn accept<Visitor: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                    self,
+                    field_value: &dyn ::std::any::Any,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) -> ::std::ops::ControlFlow<Visitor::Break> {
+                    visitor.try_visit_any(field_value, field)
+                }
+
+                pub fn accept_pair<Visitor: #visit_field_pair_trait_name>(
+                    self,
+                    field_value: &dyn ::std::any::Any,
+                    other_field_value: &dyn ::std::any::Any,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) {
+                    visitor.visit_any(field_value, other_field_value, field);
+                }
+
+                pub fn accept_type<T, Visitor: #visit_field_type_trait_name>(
+                    self,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) {
+                    visitor.visit_any(field);
+                }
+
+                pub fn build<T: ::std::any::Any, Builder: #build_field_trait_name>(
+                    self,
+                    field: #zambaga_path::FieldContext,
+                    builder: &mut Builder,
+                ) -> ::std::result::Result<T, Builder::Error> {
+                    builder.build_any(field)
+                }
+
+                pub fn map<T: ::std::any::Any, Mapper: #map_field_trait_name>(
+                    self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                    mapper: &mut Mapper,
+                ) -> ::std::result::Result<T, Mapper::Error> {
+                    mapper.map_any(field_value, field)
+                }
+
+                #any_accept_async
+            }
+
+            pub struct #implemented_visitor_acceptor_name;
+
+            impl #implemented_visitor_acceptor_name {
+                pub fn accept<T: #trait_name, Visitor: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                    self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) -> ::std::ops::ControlFlow<Visitor::Break> {
+                    visitor.try_visit_implemented(field_value, field)
+                }
+
+                pub fn accept_pair<T: #trait_name, Visitor: #visit_field_pair_trait_name>(
+                    self,
+                    field_value: &T,
+                    other_field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) {
+                    visitor.visit_implemented(field_value, other_field_value, field);
+                }
+
+                pub fn accept_type<T: #trait_name, Visitor: #visit_field_type_trait_name>(
+                    self,
+                    field: #zambaga_path::FieldContext,
+                    visitor: &mut Visitor,
+                ) {
+                    visitor.visit_implemented::<T>(field);
+                }
+
+                pub fn build<T: #trait_name, Builder: #build_field_trait_name>(
+                    self,
+                    field: #zambaga_path::FieldContext,
+                    builder: &mut Builder,
+                ) -> ::std::result::Result<T, Builder::Error> {
+                    builder.build_implemented(field)
+                }
+
+                pub fn map<T: #trait_name, Mapper: #map_field_trait_name>(
+                    self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                    mapper: &mut Mapper,
+                ) -> ::std::result::Result<T, Mapper::Error> {
+                    mapper.map_implemented(field_value, field)
+                }
+
+                #implemented_accept_async
+            }
+        }
+    });
+
+    // consts typed in terms of `Self` cannot be stored outside of the implementing type
+    let field_consts = input
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::TraitItem::Const(item_const)
+                if item_const.generics.params.is_empty() && !mentions_self(&item_const.ty) =>
+            {
+                Some((&item_const.ident, &item_const.ty))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let field_const_declarations = field_consts.iter().map(|(ident, ty)| {
+        quote! { pub #ident: ::std::option::Option<#ty>, }
+    });
+    let field_const_missing = field_consts.iter().map(|(ident, _)| {
+        quote! { #ident: ::std::option::Option::None, }
+    });
+    let field_const_present = field_consts.iter().map(|(ident, _)| {
+        quote! { #ident: ::std::option::Option::Some(<T as #trait_name>::#ident), }
+    });
+
+    let is_trait_struct = quote! {
+        #[allow(non_snake_case)]
+        pub struct #field_consts_struct_name {
+            #(#field_const_declarations)*
+        }
+
+        pub struct #is_trait_struct_name<'a, T>(pub &'a T);
+
+        impl<'a, T> #is_trait_struct_name<'a, T> {
+            pub const fn new(value: &'a T) -> Self {
+                Self(value)
+            }
+        }
+
+        pub trait #default_case_trait_name<'a> {
+            fn as_dyn(&self) -> ::std::option::Option<&'a <#mdt_struct_name as #zambaga_path::MakeDynTrait>::DynTrait<'a>> {
+                ::std::option::Option::None
+            }
+            const DOES_IMPLEMENT: bool = false;
+            const FIELD_CONSTS: #field_consts_struct_name = #field_consts_struct_name {
+                #(#field_const_missing)*
+            };
+            #default_case_visitor_acceptor
+        }
+
+        impl<'a, T> #default_case_trait_name<'a> for #is_trait_struct_name<'a, T> {}
+
+        impl<'a, T: #trait_name> #is_trait_struct_name<'a, T> {
+            #implemented_as_dyn
+
+            pub const DOES_IMPLEMENT: bool = true;
+            pub const FIELD_CONSTS: #field_consts_struct_name = #field_consts_struct_name {
+                #(#field_const_present)*
+            };
+
+            #implemented_visitor_acceptor
+        }
+
+        #visitor_acceptors
+    };
+
+    let visit_field_trait = recursive_mode.then(|| {
+        quote! {
+            pub trait #visit_field_trait_name {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                );
+
+                fn visit_any(
+                    &mut self,
+                    _field_value: &dyn ::std::any::Any,
+                    _field: #zambaga_path::FieldContext,
+                ) {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `visit_any` method."
+                    );
+                }
+            }
+
+            // Like `Visit{Trait}Field`, but for the receiver-less methods of the trait, which only
+            //  need the types of the fields.
+            pub trait #visit_field_type_trait_name {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field: #zambaga_path::FieldContext,
+                );
+
+                fn visit_any(
+                    &mut self,
+                    _field: #zambaga_path::FieldContext,
+                ) {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `visit_any` method."
+                    );
+                }
+            }
+
+            // Produces the values of the fields when building a value of the derived type, e.g. in
+            //  the trait methods returning `Self`.
+            pub trait #build_field_trait_name {
+                type Error;
+
+                fn build_implemented<T: #trait_name>(
+                    &mut self,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error>;
+
+                fn build_any<T: ::std::any::Any>(
+                    &mut self,
+                    _field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `build_any` method."
+                    );
+                }
+            }
+
+            // Like `Build{Trait}Field`, but out of the fields of a value of the derived type, e.g.
+            //  in the trait methods taking `self` and returning `Self`.
+            pub trait #map_field_trait_name {
+                type Error;
+
+                fn map_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error>;
+
+                fn map_any<T: ::std::any::Any>(
+                    &mut self,
+                    _field_value: &T,
+                    _field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `map_any` method."
+                    );
+                }
+            }
+
+            // the visitors can be lent out instead of being handed back, e.g. when they borrow
+            //  from the caller
+            impl<V: #visit_field_trait_name + ?::std::marker::Sized> #visit_field_trait_name for &mut V {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) {
+                    (**self).visit_implemented(field_value, field)
+                }
+
+                fn visit_any(
+                    &mut self,
+                    field_value: &dyn ::std::any::Any,
+                    field: #zambaga_path::FieldContext,
+                ) {
+                    (**self).visit_any(field_value, field)
+                }
+            }
+
+            // Like `Visit{Trait}Field`, but every field can stop the visitation by breaking with a
+            //  value, e.g. an error or the field that was searched for. The plain visitors never
+            //  break.
+            pub trait #try_visit_field_trait_name {
+                type Break;
+
+                fn try_visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::ops::ControlFlow<Self::Break>;
+
+                fn try_visit_any(
+                    &mut self,
+                    _field_value: &dyn ::std::any::Any,
+                    _field: #zambaga_path::FieldContext,
+                ) -> ::std::ops::ControlFlow<Self::Break> {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `try_visit_any` method."
+                    );
+                }
+            }
+
+            impl<V: #visit_field_trait_name + ?::std::marker::Sized> #try_visit_field_trait_name for V {
+                type Break = ::std::convert::Infallible;
+
+                fn try_visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::ops::ControlFlow<Self::Break> {
+                    self.visit_implemented(field_value, field);
+                    ::std::ops::ControlFlow::Continue(())
+                }
+
+                fn try_visit_any(
+                    &mut self,
+                    field_value: &dyn ::std::any::Any,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::ops::ControlFlow<Self::Break> {
+                    self.visit_any(field_value, field);
+                    ::std::ops::ControlFlow::Continue(())
+                }
+            }
+
+            // Visits the matching fields of two values of the same type together, e.g. to compare
+            //  them. Values of different enum variants have no matching fields.
+            pub trait #visit_field_pair_trait_name {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    other_field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                );
+
+                fn visit_any(
+                    &mut self,
+                    _field_value: &dyn ::std::any::Any,
+                    _other_field_value: &dyn ::std::any::Any,
+                    _field: #zambaga_path::FieldContext,
+                ) {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `visit_any` method."
+                    );
+                }
+
+                fn visit_different_variants(&mut self, _variant: #zambaga_path::VariantName, _other_variant: #zambaga_path::VariantName) {}
+            }
+
+            impl<V: #visit_field_pair_trait_name + ?::std::marker::Sized> #visit_field_pair_trait_name for &mut V {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    other_field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) {
+                    (**self).visit_implemented(field_value, other_field_value, field)
+                }
+
+                fn visit_any(
+                    &mut self,
+                    field_value: &dyn ::std::any::Any,
+                    other_field_value: &dyn ::std::any::Any,
+                    field: #zambaga_path::FieldContext,
+                ) {
+                    (**self).visit_any(field_value, other_field_value, field)
+                }
+
+                fn visit_different_variants(&mut self, variant: #zambaga_path::VariantName, other_variant: #zambaga_path::VariantName) {
+                    (**self).visit_different_variants(variant, other_variant)
+                }
+            }
+
+            // Reduces the fields to a single value, which each of them gets and gives back.
+            pub trait #fold_field_trait_name<Acc> {
+                fn fold_implemented<T: #trait_name>(
+                    &mut self,
+                    acc: Acc,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> Acc;
+
+                fn fold_any(
+                    &mut self,
+                    _acc: Acc,
+                    _field_value: &dyn ::std::any::Any,
+                    _field: #zambaga_path::FieldContext,
+                ) -> Acc {
+                    ::std::panic!(
+                        "This should not be called. If this is being called it means that you turned off \
+                        the validator but didn't override the `fold_any` method."
+                    );
+                }
+            }
+
+            // the accumulator is only ever missing while a field is being folded
+            struct #field_folder_struct_name<Acc, F> {
+                acc: ::std::option::Option<Acc>,
+                folder: F,
+            }
+
+            impl<Acc, F: #fold_field_trait_name<Acc>> #visit_field_trait_name for #field_folder_struct_name<Acc, F> {
+                fn visit_implemented<T: #trait_name>(&mut self, field_value: &T, field: #zambaga_path::FieldContext) {
+                    let acc = self.acc.take().unwrap();
+                    self.acc = ::std::option::Option::Some(self.folder.fold_implemented(acc, field_value, field));
+                }
+
+                fn visit_any(&mut self, field_value: &dyn ::std::any::Any, field: #zambaga_path::FieldContext) {
+                    let acc = self.acc.take().unwrap();
+                    self.acc = ::std::option::Option::Some(self.folder.fold_any(acc, field_value, field));
+                }
+            }
+
+            impl<V: #visit_field_type_trait_name + ?::std::marker::Sized> #visit_field_type_trait_name for &mut V {
+                fn visit_implemented<T: #trait_name>(
+                    &mut self,
+                    field: #zambaga_path::FieldContext,
+                ) {
+                    (**self).visit_implemented::<T>(field)
+                }
+
+                fn visit_any(&mut self, field: #zambaga_path::FieldContext) {
+                    (**self).visit_any(field)
+                }
+            }
+
+            impl<B: #build_field_trait_name + ?::std::marker::Sized> #build_field_trait_name for &mut B {
+                type Error = B::Error;
+
+                fn build_implemented<T: #trait_name>(
+                    &mut self,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    (**self).build_implemented(field)
+                }
+
+                fn build_any<T: ::std::any::Any>(
+                    &mut self,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    (**self).build_any(field)
+                }
+            }
+
+            impl<M: #map_field_trait_name + ?::std::marker::Sized> #map_field_trait_name for &mut M {
+                type Error = M::Error;
+
+                fn map_implemented<T: #trait_name>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    (**self).map_implemented(field_value, field)
+                }
+
+                fn map_any<T: ::std::any::Any>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> ::std::result::Result<T, Self::Error> {
+                    (**self).map_any(field_value, field)
+                }
+            }
+        }
+    });
+
+    let visit_field_async_trait = async_mode.then(|| {
+        quote! {
+            // Like `Visit{Trait}Field`, but awaited field by field, for the async methods of the
+            //  trait.
+            pub trait #visit_field_async_trait_name {
+                fn visit_implemented<T: #trait_name #sync_bound>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> impl ::std::future::Future<Output = ()> #send_bound;
+
+                fn visit_any(
+                    &mut self,
+                    _field_value: &(dyn ::std::any::Any #sync_bound),
+                    _field: #zambaga_path::FieldContext,
+                ) -> impl ::std::future::Future<Output = ()> #send_bound {
+                    async {
+                        ::std::panic!(
+                            "This should not be called. If this is being called it means that you turned off \
+                            the validator but didn't override the `visit_any` method."
+                        );
+                    }
+                }
+            }
+
+            impl<V: #visit_field_async_trait_name + ?::std::marker::Sized> #visit_field_async_trait_name for &mut V {
+                fn visit_implemented<T: #trait_name #sync_bound>(
+                    &mut self,
+                    field_value: &T,
+                    field: #zambaga_path::FieldContext,
+                ) -> impl ::std::future::Future<Output = ()> #send_bound {
+                    (**self).visit_implemented(field_value, field)
+                }
+
+                fn visit_any(
+                    &mut self,
+                    field_value: &(dyn ::std::any::Any #sync_bound),
+                    field: #zambaga_path::FieldContext,
+                ) -> impl ::std::future::Future<Output = ()> #send_bound {
+                    (**self).visit_any(field_value, field)
+                }
+            }
+        }
+    });
+
+    let required_items = input
+        .items
+        .iter()
+        .filter(|item| !is_default_item(item) && !is_default_type(item));
+
+    let visit_fields_method = recursive_mode.then(|| {
+        quote! {
+            fn visit_fields<V: #visit_field_trait_name>(&self, visitor: V) -> V {
+                self.visit_field_values(visitor)
+            }
+
+            fn try_visit_fields<V: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                &self,
+                visitor: &mut V,
+            ) -> ::std::ops::ControlFlow<V::Break> {
+                self.try_visit_field_values(visitor)
+            }
+
+            fn try_visit_fields_result<V: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                &self,
+                visitor: &mut V,
+            ) -> ::std::result::Result<(), V::Break> {
+                self.try_visit_field_values_result(visitor)
+            }
 
-    let visit_field_trait_name = make_ident!("Visit{}Field", trait_name);
-    let derive_trait_name = make_ident!("Derive{}", trait_name);
-    let mdt_struct_name = make_ident!("MDT{}", trait_name);
-    let is_trait_struct_name = make_ident!("Is{}", trait_name);
-    let impl_make_dyn_trait_macro_name = make_ident!("{}_field_visitor", trait_name_snake_case);
-    let impl_module_name = make_ident!("__zambaga_{}_impl", trait_name_snake_case);
-    let impl_trait_macro_name = make_ident!("{}Macro", trait_name);
+            fn fold_fields<Acc, F: #fold_field_trait_name<Acc>>(&self, init: Acc, folder: F) -> Acc {
+                self.fold_field_values(init, folder)
+            }
 
-    let mdt = quote! {
-        pub struct #mdt_struct_name;
+            fn zip_fields<V: #visit_field_pair_trait_name>(&self, other: &Self, visitor: V) -> V {
+                self.zip_field_values(other, visitor)
+            }
+        }
+    });
 
-        macro_rules! #impl_make_dyn_trait_macro_name {
-            ($visitor:path) => {
-                impl ::zambaga::MakeDynTrait for #impl_module_name::#mdt_struct_name {
-                    type DynTrait<'a> = dyn #trait_name + 'a;
-                    type IsTrait<'a, T: 'a> = #impl_module_name::#is_trait_struct_name<'a, T>;
-                    type FieldVisitor = $visitor;
-                    const TRAIT_NAME: &'static str = #trait_name_str_literal;
+    let visit_field_values_async_declaration = async_mode.then(|| {
+        quote! {
+            fn visit_field_values_async<V: #visit_field_async_trait_name #send_bound>(
+                &self,
+                visitor: V,
+            ) -> impl ::std::future::Future<Output = V> #send_bound;
+        }
+    });
+    let visit_field_values_async_method = async_mode.then(|| {
+        quote! {
+            fn visit_field_values_async<__ZambagaVisitor: #impl_module_path::#visit_field_async_trait_name #send_bound>(
+                &self,
+                mut visitor: __ZambagaVisitor,
+            ) -> impl ::std::future::Future<Output = __ZambagaVisitor> #send_bound {
+                async move {
+                    #[allow(unused_variables)]
+                    let $async_this = self;
+                    #[allow(unused_variables)]
+                    let $async_visitor = &mut visitor;
+                    $visit_async_body
+                    visitor
                 }
-            };
+            }
         }
-        pub(crate) use #impl_make_dyn_trait_macro_name;
-    };
+    });
+    let reflect_trait_methods = recursive_mode.then(|| {
+        quote! {
+            // `visit_fields` and friends, for the derivation functions which only know the type to
+            //  be reflected
+            fn visit_field_values<V: #visit_field_trait_name>(&self, mut visitor: V) -> V {
+                match self.try_visit_field_values(&mut visitor) {
+                    ::std::ops::ControlFlow::Continue(()) => visitor,
+                    ::std::ops::ControlFlow::Break(never) => match never {},
+                }
+            }
 
-    let is_trait_struct = quote! {
-        pub struct #is_trait_struct_name<'a, T>(pub &'a T);
+            // stops at the first field the visitor breaks on
+            fn try_visit_field_values<V: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                &self,
+                visitor: &mut V,
+            ) -> ::std::ops::ControlFlow<V::Break>;
 
-        impl<'a, T> #is_trait_struct_name<'a, T> {
-            pub const fn new(value: &'a T) -> Self {
-                Self(value)
+            // the same, for the visitors breaking with errors
+            fn try_visit_field_values_result<V: #try_visit_field_trait_name + ?::std::marker::Sized>(
+                &self,
+                visitor: &mut V,
+            ) -> ::std::result::Result<(), V::Break> {
+                match self.try_visit_field_values(visitor) {
+                    ::std::ops::ControlFlow::Continue(()) => ::std::result::Result::Ok(()),
+                    ::std::ops::ControlFlow::Break(error) => ::std::result::Result::Err(error),
+                }
             }
-        }
 
-        pub trait DefaultCase<'a> {
-            fn as_dyn(&self) -> Option<&'a dyn #trait_name> {
-                None
+            fn fold_field_values<Acc, F: #fold_field_trait_name<Acc>>(&self, init: Acc, folder: F) -> Acc {
+                let folder = self.visit_field_values(#field_folder_struct_name {
+                    acc: ::std::option::Option::Some(init),
+                    folder,
+                });
+                folder.acc.unwrap()
             }
-            const DOES_IMPLEMENT: bool = false;
-            const VISITOR_ACCEPTOR: AnyVisitorAcceptor = AnyVisitorAcceptor;
+
+            fn zip_field_values<V: #visit_field_pair_trait_name>(&self, other: &Self, visitor: V) -> V;
+
+            #visit_field_values_async_declaration
+
+            fn visit_field_types<V: #visit_field_type_trait_name>(visitor: V) -> V;
+
+            fn build_fields<B: #build_field_trait_name>(builder: B) -> ::std::result::Result<Self, B::Error>;
+
+            // builds the same struct or variant out of the fields of `self`
+            fn map_fields<M: #map_field_trait_name>(&self, mapper: M) -> ::std::result::Result<Self, M::Error>;
         }
+    });
 
-        impl<'a, T> DefaultCase<'a> for #is_trait_struct_name<'a, T> {}
+    let reflect_trait = quote! {
+        // Implemented by `#[z::derive]` next to `WithMirror`, for the parts of the reflection that
+        //  need to be generic over the visitors.
+        pub trait #reflect_trait_name: #zambaga_path::WithMirror<#mdt_struct_name> {
+            #reflect_trait_methods
+        }
+    };
 
-        impl<'a, T: #trait_name> #is_trait_struct_name<'a, T> {
-            pub fn as_dyn(&self) -> Option<&'a dyn #trait_name> {
-                Some(self.0 as &dyn #trait_name)
+    let context_methods = recursive_mode.then(|| {
+        quote! {
+            pub fn visit_field_types<V: #visit_field_type_trait_name>(&self, visitor: V) -> V {
+                T::visit_field_types(visitor)
             }
 
-            pub const DOES_IMPLEMENT: bool = true;
-
-            pub const VISITOR_ACCEPTOR: ImplementedVisitorAcceptor = ImplementedVisitorAcceptor;
+            pub fn build_fields<B: #build_field_trait_name>(&self, builder: B) -> ::std::result::Result<T, B::Error> {
+                T::build_fields(builder)
+            }
         }
+    });
 
-        pub struct AnyVisitorAcceptor;
+    // Passed to the `#[z::derivation]` functions of the methods which don't have a `self` to look
+    //  at, i.e. the receiver-less ones and the ones returning `Self`.
+    let context_struct = quote! {
+        pub struct #context_struct_name<T>(::std::marker::PhantomData<fn() -> T>);
 
-        impl AnyVisitorAcceptor {
-            pub fn accept<Visitor: #visit_field_trait_name>(
-                self,
-                field_value: &dyn Any,
-                field_name: Option<FieldName>,
-                field_type: TypeName,
-                visitor: &mut Visitor,
-            ) {
-                visitor.visit_any(field_value, field_name, field_type);
+        impl<T> ::std::clone::Clone for #context_struct_name<T> {
+            fn clone(&self) -> Self {
+                *self
             }
         }
 
-        pub struct ImplementedVisitorAcceptor;
+        impl<T> ::std::marker::Copy for #context_struct_name<T> {}
 
-        impl ImplementedVisitorAcceptor {
-            pub fn accept<T: #trait_name, Visitor: #visit_field_trait_name>(
-                self,
-                field_value: &T,
-                field_name: Option<FieldName>,
-                field_type: TypeName,
-                visitor: &mut Visitor,
-            ) {
-                visitor.visit_implemented(field_value, field_name, field_type);
+        impl<T: #reflect_trait_name> ::std::default::Default for #context_struct_name<T> {
+            fn default() -> Self {
+                Self::new()
             }
         }
-    };
 
-    let visit_field_trait = quote! {
-        pub trait #visit_field_trait_name {
-            fn visit_implemented<T: #trait_name>(
-                &mut self,
-                field_value: &T,
-                field_name: Option<FieldName>,
-                field_type: TypeName,
-            );
-
-            fn visit_any(
-                &mut self,
-                _field_value: &dyn Any,
-                _field_name: Option<FieldName>,
-                _field_type: TypeName,
-            ) {
-                panic!(
-                    "This should not be called. If this is being called it means that you turned off \
-                    the validator but didn't override the `visit_any` method."
-                );
+        impl<T: #reflect_trait_name> #context_struct_name<T> {
+            pub const fn new() -> Self {
+                Self(::std::marker::PhantomData)
             }
+
+            pub const fn mirror(&self) -> #zambaga_path::Mirror<#mdt_struct_name> {
+                T::MIRROR
+            }
+
+            #context_methods
         }
     };
 
-    let trait_item_declarations = input.items.clone();
+    let reflect_arm = if recursive_mode {
+        quote! {
+            (
+                @reflect $name:ty;
+                |$this:ident, $value_visitor:ident| $visit_values_body:block;
+                |$zip_this:ident, $zip_other:ident, $zip_visitor:ident| $zip_body:block;
+                async |$async_this:ident, $async_visitor:ident| $visit_async_body:block;
+                |$visitor:ident| $visit_body:block;
+                |$builder:ident| $build_body:block;
+                |$map_this:ident, $mapper:ident| $map_body:block
+            ) => {
+                // the generic parameters can't be named like the types of the deriving crate,
+                //  which end up in the bodies
+                impl #impl_module_path::#reflect_trait_name for $name {
+                    fn try_visit_field_values<__ZambagaVisitor: #impl_module_path::#try_visit_field_trait_name + ?::std::marker::Sized>(
+                        &self,
+                        visitor: &mut __ZambagaVisitor,
+                    ) -> ::std::ops::ControlFlow<__ZambagaVisitor::Break> {
+                        #[allow(unused_variables)]
+                        let $this = self;
+                        #[allow(unused_variables)]
+                        let $value_visitor = visitor;
+                        $visit_values_body
+                        ::std::ops::ControlFlow::Continue(())
+                    }
 
-    let derive_trait = quote! {
-        pub trait #derive_trait_name: WithMirror<#mdt_struct_name> {
-            const VALIDATION: Validation = Validation::all_fields_impl(&Self::MIRROR);
+                    fn zip_field_values<__ZambagaVisitor: #impl_module_path::#visit_field_pair_trait_name>(
+                        &self,
+                        other: &Self,
+                        mut visitor: __ZambagaVisitor,
+                    ) -> __ZambagaVisitor {
+                        #[allow(unused_variables)]
+                        let ($zip_this, $zip_other) = (self, other);
+                        #[allow(unused_variables)]
+                        let $zip_visitor = &mut visitor;
+                        $zip_body
+                        visitor
+                    }
 
-            #(#trait_item_declarations)*
+                    #visit_field_values_async_method
 
-            fn visit_fields(
-                &self,
-                mut visitor: <#mdt_struct_name as MakeDynTrait>::FieldVisitor,
-            ) -> <#mdt_struct_name as MakeDynTrait>::FieldVisitor {
-                Self::MIRROR
-                    .field_traverser
-                    .accept::<Self>(&mut visitor, self);
+                    fn visit_field_types<__ZambagaVisitor: #impl_module_path::#visit_field_type_trait_name>(
+                        mut visitor: __ZambagaVisitor,
+                    ) -> __ZambagaVisitor {
+                        #[allow(unused_variables)]
+                        let $visitor = &mut visitor;
+                        $visit_body
+                        visitor
+                    }
 
-                visitor
-            }
+                    fn build_fields<__ZambagaBuilder: #impl_module_path::#build_field_trait_name>(
+                        mut builder: __ZambagaBuilder,
+                    ) -> ::std::result::Result<Self, __ZambagaBuilder::Error> {
+                        #[allow(unused_variables)]
+                        let $builder = &mut builder;
+                        $build_body
+                    }
+
+                    fn map_fields<__ZambagaMapper: #impl_module_path::#map_field_trait_name>(
+                        &self,
+                        mut mapper: __ZambagaMapper,
+                    ) -> ::std::result::Result<Self, __ZambagaMapper::Error> {
+                        #[allow(unused_variables)]
+                        let $map_this = self;
+                        #[allow(unused_variables)]
+                        let $mapper = &mut mapper;
+                        $map_body
+                    }
+                }
+            };
+        }
+    } else {
+        quote! {
+            (
+                @reflect $name:ty;
+                |$this:ident, $value_visitor:ident| $visit_values_body:block;
+                |$zip_this:ident, $zip_other:ident, $zip_visitor:ident| $zip_body:block;
+                async |$async_this:ident, $async_visitor:ident| $visit_async_body:block;
+                |$visitor:ident| $visit_body:block;
+                |$builder:ident| $build_body:block;
+                |$map_this:ident, $mapper:ident| $map_body:block
+            ) => {
+                impl #impl_module_path::#reflect_trait_name for $name {}
+            };
         }
     };
 
-    let make_forward_trait_items = |for_derivation| {
-        input
-            .items
-            .iter()
-            .map(|item| match item.clone() {
-                syn::TraitItem::Const(syn::TraitItemConst {
-                    attrs,
-                    const_token,
-                    ident,
-                    generics,
-                    colon_token,
-                    ty,
-                    ..
-                }) => syn::ImplItemConst {
-                    attrs,
-                    vis: syn::Visibility::Inherited,
-                    defaultness: Default::default(),
-                    const_token,
-                    ident: ident.clone(),
-                    generics,
-                    colon_token,
-                    ty,
-                    eq_token: Default::default(),
-                    expr: syn::parse2(if for_derivation {
-                        quote! {
-                            derivation::#ident
+    // the field visitor of a `#[z::derivation]` module, made of its `visit_implemented` and
+    //  `visit_any` functions taking the visitor as their first argument
+    let derivation_visitor_arm = if recursive_mode {
+        quote! {
+            (
+                @derivation_visitor $visitor:ty;
+                $visit_implemented:path;
+                $($visit_any:path)?
+            ) => {
+                impl #impl_module_path::#visit_field_trait_name for $visitor {
+                    fn visit_implemented<T: #trait_path>(
+                        &mut self,
+                        field_value: &T,
+                        field: #impl_module_path::zambaga::FieldContext,
+                    ) {
+                        $visit_implemented(self, field_value, field)
+                    }
+
+                    $(
+                        fn visit_any(
+                            &mut self,
+                            field_value: &dyn ::std::any::Any,
+                            field: #impl_module_path::zambaga::FieldContext,
+                        ) {
+                            $visit_any(self, field_value, field)
                         }
-                    } else {
-                        quote! {
-                            <Self as #impl_module_name::#derive_trait_name>::#ident
+                    )?
+                }
+            };
+        }
+    } else {
+        quote! {
+            (@derivation_visitor $($tokens:tt)*) => {
+                ::std::compile_error!(
+                    "field visitors need the `recursive` reflection mode of the trait"
+                );
+            };
+        }
+    };
+
+    // syn can't parse `$crate`, so the items are parsed with placeholders in place of the paths,
+    //  including the one of the trait the derived types forward to
+    let make_forward_trait_item_syntax = |item: &syn::TraitItem, for_derivation: bool| {
+        match item.clone() {
+            syn::TraitItem::Const(syn::TraitItemConst {
+                attrs,
+                const_token,
+                ident,
+                generics,
+                colon_token,
+                ty,
+                ..
+            }) => syn::ImplItemConst {
+                attrs,
+                vis: syn::Visibility::Inherited,
+                defaultness: Default::default(),
+                const_token,
+                ident: ident.clone(),
+                generics,
+                colon_token,
+                ty,
+                eq_token: Default::default(),
+                expr: if for_derivation {
+                    // derivations compute consts in a const fn, so they can fold over the fields
+                    syn::parse_quote! {
+                        derivation::#ident(__zambaga_impl_module::#context_struct_name::<Self>::new())
+                    }
+                } else {
+                    syn::parse_quote! {
+                        <Self as __zambaga_forward_trait>::#ident
+                    }
+                },
+                semi_token: Default::default(),
+            }
+            .to_token_stream(),
+            syn::TraitItem::Fn(syn::TraitItemFn { attrs, mut sig, .. }) => {
+                let ident = sig.ident.clone();
+                let has_receiver = sig.receiver().is_some();
+                let context = (for_derivation
+                    && (!has_receiver || mentions_self_type(&sig.output)))
+                .then(|| quote! { __zambaga_impl_module::#context_struct_name::<Self>::new() });
+                // patterns (`mut x`, `ref x`, `(a, b)`, `_`) can't be passed on as expressions,
+                //  so the arguments other than plain names get bound to fresh ones instead
+                let args = context
+                    .into_iter()
+                    .chain(sig.inputs.iter_mut().enumerate().map(|(i, arg)| match arg {
+                        syn::FnArg::Receiver(receiver) => {
+                            // `mut self` only rebinds, but `&mut self` is part of the type
+                            if receiver.reference.is_none() {
+                                receiver.mutability = None;
+                            }
+                            quote! { self }
                         }
-                    })
-                    .unwrap(),
-                    semi_token: Default::default(),
-                }
-                .to_token_stream(),
-                syn::TraitItem::Fn(syn::TraitItemFn { attrs, sig, .. }) => {
-                    let ident = sig.ident.clone();
-                    let args = sig
-                        .inputs
-                        .iter()
-                        .map(|arg| match arg {
-                            syn::FnArg::Receiver(_) => quote! { self },
-                            syn::FnArg::Typed(pat_type) => {
-                                // TODO: convert pattern to an expression properly
-                                // * strip mut
-                                // * handle ref
-                                pat_type.pat.to_token_stream()
+                        syn::FnArg::Typed(pat_type) => match &*pat_type.pat {
+                            syn::Pat::Ident(syn::PatIdent {
+                                by_ref: None,
+                                mutability: None,
+                                ident,
+                                subpat: None,
+                                ..
+                            }) => ident.to_token_stream(),
+                            _ => {
+                                let arg_name = make_ident!("__zambaga_arg{}", i);
+                                *pat_type.pat = syn::parse_quote! { #arg_name };
+                                arg_name.to_token_stream()
                             }
-                        })
-                        .collect::<Vec<_>>();
-
-                    syn::ImplItemFn {
-                        attrs,
-                        vis: syn::Visibility::Inherited,
-                        defaultness: Default::default(),
-                        sig,
-                        block: syn::parse2(if for_derivation {
-                            quote! {{
-                                derivation::#ident(#(#args),*)
-                            }}
-                        } else {
-                            quote! {{
-                                <Self as #impl_module_name::#derive_trait_name>::#ident(#(#args),*)
-                            }}
-                        })
-                        .inspect_err(|e| {
-                            eprintln!("hello????? here {}", e);
-                        })
-                        .unwrap(),
-                    }
-                    .to_token_stream()
-                }
-                syn::TraitItem::Type(syn::TraitItemType {
+                        },
+                    }))
+                    .collect::<Vec<_>>();
+                // lifetimes are left to inference, as late bound ones can't be specified
+                let generic_args = sig
+                    .generics
+                    .params
+                    .iter()
+                    .filter_map(|param| match param {
+                        syn::GenericParam::Type(param) => Some(&param.ident),
+                        syn::GenericParam::Const(param) => Some(&param.ident),
+                        syn::GenericParam::Lifetime(_) => None,
+                    })
+                    .collect::<Vec<_>>();
+                let call = match (for_derivation, generic_args.is_empty()) {
+                    (true, true) => quote! { derivation::#ident(#(#args),*) },
+                    // derivations see the implementing type as the first type parameter,
+                    //  followed by the ones of the method
+                    (true, false) => quote! {
+                        derivation::#ident::<Self, #(#generic_args),*>(#(#args),*)
+                    },
+                    (false, _) => quote! {
+                        <Self as __zambaga_forward_trait>::#ident::<#(#generic_args),*>(#(#args),*)
+                    },
+                };
+                let call = if sig.unsafety.is_some() {
+                    quote! { unsafe { #call } }
+                } else {
+                    call
+                };
+                let call = if sig.asyncness.is_some() {
+                    quote! { #call.await }
+                } else {
+                    call
+                };
+
+                syn::ImplItemFn {
                     attrs,
-                    type_token,
-                    ident,
-                    generics,
-                    semi_token,
-                    ..
-                }) => syn::ImplItemType {
+                    vis: syn::Visibility::Inherited,
+                    defaultness: Default::default(),
+                    sig,
+                    block: syn::parse_quote! {{ #call }},
+                }
+                .to_token_stream()
+            }
+            syn::TraitItem::Type(syn::TraitItemType {
+                attrs,
+                type_token,
+                ident,
+                generics,
+                semi_token,
+                ..
+            }) => {
+                let lifetime_args = generics.lifetimes().map(|param| &param.lifetime);
+                let type_args = generics.type_params().map(|param| &param.ident);
+                let const_args = generics.const_params().map(|param| &param.ident);
+                let ty = if for_derivation {
+                    // derivations see the implementing type as the first type parameter of the alias
+                    quote! {
+                        derivation::#ident<#(#lifetime_args,)* Self, #(#type_args,)* #(#const_args,)*>
+                    }
+                } else if generics.params.is_empty() {
+                    quote! {
+                        <Self as __zambaga_forward_trait>::#ident
+                    }
+                } else {
+                    quote! {
+                        <Self as __zambaga_forward_trait>::#ident<
+                            #(#lifetime_args,)* #(#type_args,)* #(#const_args,)*
+                        >
+                    }
+                };
+
+                syn::ImplItemType {
                     attrs,
                     vis: syn::Visibility::Inherited,
                     defaultness: Default::default(),
@@ -371,85 +1835,326 @@ fn reflect_impl(_attributes: TokenStream, item: TokenStream) -> TokenStream {
                     ident: ident.clone(),
                     generics,
                     eq_token: Default::default(),
-                    ty: syn::parse2(if for_derivation {
-                        quote! {{
-                            derivation::#ident
-                        }}
-                    } else {
-                        quote! {
-                            <Self as #impl_module_name::#derive_trait_name>::#ident
-                        }
-                    })
-                    .inspect_err(|e| {
-                        eprintln!("hello????? {}", e);
-                    })
-                    .unwrap(),
+                    ty: syn::parse_quote!(#ty),
                     semi_token,
                 }
-                .to_token_stream(),
-                _ => unimplemented!(),
+                .to_token_stream()
+            }
+            // rejected by `check_reflected_trait`
+            _ => TokenStream::new(),
+        }
+    };
+
+    let forward_to = |item: &syn::TraitItem, for_derivation: bool, forward_trait: &TokenStream| {
+        replace_ident(
+            replace_ident(
+                make_forward_trait_item_syntax(item, for_derivation),
+                "__zambaga_forward_trait",
+                forward_trait,
+            ),
+            "__zambaga_impl_module",
+            &impl_module_path,
+        )
+    };
+    let make_forward_trait_item = |item: &syn::TraitItem, for_derivation: bool| {
+        forward_to(item, for_derivation, &quote! { #impl_module_path::#derive_trait_name })
+    };
+
+    // The trait's own defaults move to `{Trait}DefaultItems`, renamed so that their bodies still
+    //  see the items of the trait, and the trait forwards to them. `Derive{Trait}` falls back on
+    //  them too, so that the derived types get the defaults the derivation doesn't override.
+    let default_item_name =
+        |ident: &Ident| Ident::new(&format!("__zambaga_default_{}", ident), ident.span());
+    let forward_to_default = |item: &syn::TraitItem, defaults_trait: &TokenStream| match item {
+        syn::TraitItem::Const(item) => {
+            let ident = default_item_name(&item.ident);
+            syn::TraitItem::Const(syn::TraitItemConst {
+                default: Some((
+                    Default::default(),
+                    syn::parse_quote! { <Self as #defaults_trait>::#ident },
+                )),
+                ..item.clone()
             })
-            .collect::<Vec<_>>()
+        }
+        syn::TraitItem::Fn(item) => {
+            let mut renamed = item.clone();
+            renamed.sig.ident = default_item_name(&item.sig.ident);
+            let forward = syn::parse2::<syn::ImplItemFn>(make_forward_trait_item_syntax(
+                &syn::TraitItem::Fn(renamed),
+                false,
+            ))
+            .unwrap();
+            syn::TraitItem::Fn(syn::TraitItemFn {
+                attrs: forward.attrs,
+                sig: syn::Signature {
+                    ident: item.sig.ident.clone(),
+                    ..forward.sig
+                },
+                default: Some(forward.block),
+                semi_token: None,
+            })
+        }
+        item => item.clone(),
+    };
+    let mut reflected_trait = input.clone();
+    for item in &mut reflected_trait.items {
+        if is_default_item(item) {
+            *item = forward_to_default(item, &quote! { __zambaga_forward_trait });
+        }
+    }
+    let reflected_trait = replace_ident(
+        reflected_trait.into_token_stream(),
+        "__zambaga_forward_trait",
+        &quote! { #impl_module_name::#default_items_trait_name },
+    );
+    let default_items = input.items.iter().filter(|item| is_default_item(item)).map(|item| {
+        match item.clone() {
+            syn::TraitItem::Const(item) => syn::TraitItem::Const(syn::TraitItemConst {
+                ident: default_item_name(&item.ident),
+                ..item
+            }),
+            syn::TraitItem::Fn(mut item) => {
+                item.sig.ident = default_item_name(&item.sig.ident);
+                syn::TraitItem::Fn(item)
+            }
+            item => item,
+        }
+    });
+    // the declarations are copied from the trait, which already got linted
+    let allow_async_fn_in_trait = has_async_methods.then(|| quote! { #[allow(async_fn_in_trait)] });
+    let default_items_trait = has_default_items.then(|| {
+        quote! {
+            #[doc(hidden)]
+            #allow_async_fn_in_trait
+            #[allow(non_snake_case, non_upper_case_globals)]
+            pub trait #default_items_trait_name: #trait_name {
+                #(#default_items)*
+            }
+
+            impl<T: #trait_name + ?::std::marker::Sized> #default_items_trait_name for T {}
+        }
+    });
+    // the defaults of the consts can't be bounded by the trait, so `None` stands for them instead
+    let default_item_declarations = input
+        .items
+        .iter()
+        .filter(|item| is_default_item(item))
+        .map(|item| match item {
+            syn::TraitItem::Const(syn::TraitItemConst { attrs, ident, ty, .. }) => quote! {
+                #(#attrs)*
+                const #ident: ::std::option::Option<#ty> = ::std::option::Option::None;
+            },
+            item => {
+                let mut declaration =
+                    forward_to_default(item, &quote! { __zambaga_forward_trait });
+                if let syn::TraitItem::Fn(declaration) = &mut declaration {
+                    declaration
+                        .sig
+                        .generics
+                        .make_where_clause()
+                        .predicates
+                        .push(syn::parse_quote! { Self: #trait_name });
+                }
+                replace_ident(
+                    declaration.into_token_stream(),
+                    "__zambaga_forward_trait",
+                    &default_items_trait_name.to_token_stream(),
+                )
+            }
+        });
+    let derive_trait = quote! {
+        #allow_async_fn_in_trait
+        pub trait #derive_trait_name: #reflect_trait_name {
+            const VALIDATION: #zambaga_path::Validation = #zambaga_path::Validation::all_fields_impl(&Self::MIRROR);
+
+            #(#required_items)*
+
+            #(#default_item_declarations)*
+
+            #visit_fields_method
+        }
     };
 
-    let forward_trait_items = make_forward_trait_items(false);
-    let forward_trait_items_for_derivation_syntax = make_forward_trait_items(true);
+    let forward_trait_items = input
+        .items
+        .iter()
+        .filter(|item| !is_default_type(item))
+        .map(|item| match item {
+            syn::TraitItem::Const(syn::TraitItemConst {
+                attrs,
+                ident,
+                ty,
+                default: Some(_),
+                ..
+            }) => {
+                let default_ident = default_item_name(ident);
+                quote! {
+                    #(#attrs)*
+                    const #ident: #ty = match <Self as #impl_module_path::#derive_trait_name>::#ident {
+                        ::std::option::Option::Some(value) => value,
+                        ::std::option::Option::None => {
+                            <Self as #impl_module_path::#default_items_trait_name>::#default_ident
+                        }
+                    };
+                }
+            }
+            item => make_forward_trait_item(item, false),
+        });
+    let forward_trait_items_for_derivation_syntax = input
+        .items
+        .iter()
+        .filter(|item| !is_default_item(item) && !is_default_type(item))
+        .map(|item| make_forward_trait_item(item, true));
+    let forward_default_arms = input.items.iter().filter_map(|item| {
+        let (ident, forward) = match item {
+            syn::TraitItem::Const(syn::TraitItemConst {
+                attrs,
+                ident,
+                ty,
+                default: Some(_),
+                ..
+            }) => (
+                ident,
+                quote! {
+                    #(#attrs)*
+                    const #ident: ::std::option::Option<#ty> = ::std::option::Option::Some(
+                        derivation::#ident(#impl_module_path::#context_struct_name::<Self>::new())
+                    );
+                },
+            ),
+            syn::TraitItem::Fn(item_fn) if item_fn.default.is_some() => {
+                (&item_fn.sig.ident, make_forward_trait_item(item, true))
+            }
+            _ => return None,
+        };
+        Some(quote! {
+            (@forward_default #ident) => {
+                #forward
+            };
+        })
+    });
+    let forward_defaults = has_default_items.then(|| {
+        quote! {
+            $(#impl_module_path::#impl_trait_macro_name!(@forward_default $provided);)*
+        }
+    });
+    let forward_default_arms = has_default_items.then(|| {
+        quote! {
+            #(#forward_default_arms)*
+
+            // the derivation doesn't override the default, or the item isn't a trait item at all
+            (@forward_default $other:ident) => {};
+        }
+    });
 
     let impl_trait_macro = quote! {
         #[macro_export]
-        macro_rules! #impl_trait_macro_name {
+        macro_rules! #exported_macro_name {
             ($name:ident) => {
-                const _: Validation = <$name as #impl_module_name::#derive_trait_name>::VALIDATION;
+                const _: #impl_module_path::zambaga::Validation = <$name as #impl_module_path::#derive_trait_name>::VALIDATION;
 
-                impl #trait_name for $name {
+                impl #trait_path for $name {
                     #(#forward_trait_items)*
                 }
             };
 
             (@MDT) => {
-                #impl_module_name::#mdt_struct_name
+                #impl_module_path::#mdt_struct_name
+            };
+
+            (@validation $name:ty) => {
+                <$name as #impl_module_path::#derive_trait_name>::VALIDATION
             };
 
-            (@blanket_impl $derivation:path; $validation:expr) => {
+
+            #reflect_arm
+
+            #derivation_visitor_arm
+
+            (
+                @blanket_impl $derivation:path;
+                |$context:ident| $validation:expr;
+                [$($provided:ident),*];
+                $($predicates:tt)*
+            ) => {
                 const _: () = {
                     use $derivation as derivation;
-                    impl<T> #derive_trait_name for T
-                    where T: ::zambaga::WithMirror<#trait_name!(@MDT)> {
-                        const VALIDATION: ::zambaga::Validation = $validation;
+                    impl<__ZambagaT> #impl_module_path::#derive_trait_name for __ZambagaT
+                    where __ZambagaT: #impl_module_path::#reflect_trait_name, $($predicates)* {
+                        const VALIDATION: #impl_module_path::zambaga::Validation = {
+                            #[allow(unused_variables)]
+                            let $context = #impl_module_path::#context_struct_name::<Self>::new();
+                            $validation
+                        };
                         #(#forward_trait_items_for_derivation_syntax)*
+                        #forward_defaults
                     }
                 };
             };
 
+            #forward_default_arms
+
+            // the default case of `Is{Trait}` has to be in scope for the fields not implementing
+            //  the trait, but not under a name which could shadow the ones of the deriving crate
             { @uses; $($i:item)* } => {
                 const _: () = {
-                    use #impl_module_name::*;
-                    use ::zambaga::*;
+                    #[allow(unused_imports)]
+                    use #impl_module_path::#default_case_trait_name as _;
                     $($i)*
                 };
             };
         }
-        pub use #impl_trait_macro_name as #impl_trait_macro_name;
+        pub use #exported_macro_name as #impl_trait_macro_name;
     };
 
+    let async_mode_reexports = async_mode.then(|| {
+        quote! {
+            pub(crate) use #impl_module_name::#visit_field_async_trait_name as #visit_field_async_trait_name;
+        }
+    });
+    let recursive_mode_reexports = recursive_mode.then(|| {
+        quote! {
+            pub(crate) use #impl_module_name::#visit_field_trait_name as #visit_field_trait_name;
+            pub(crate) use #impl_module_name::#try_visit_field_trait_name as #try_visit_field_trait_name;
+            pub(crate) use #impl_module_name::#fold_field_trait_name as #fold_field_trait_name;
+            pub(crate) use #impl_module_name::#visit_field_pair_trait_name as #visit_field_pair_trait_name;
+            pub(crate) use #impl_module_name::#visit_field_type_trait_name as #visit_field_type_trait_name;
+            pub(crate) use #impl_module_name::#build_field_trait_name as #build_field_trait_name;
+            pub(crate) use #impl_module_name::#map_field_trait_name as #map_field_trait_name;
+        }
+    });
+
     let res = quote! {
-        #input
+        #reflected_trait
 
         pub mod #impl_module_name {
             use super::*;
-            use ::zambaga::*;
-            use ::std::any::Any;
+            // for the macro arms, the deriving crates might only have it through another crate
+            pub use #zambaga_path as zambaga;
             #mdt
             #is_trait_struct
             #visit_field_trait
+            #visit_field_async_trait
+            #reflect_trait
+            #context_struct
+            #default_items_trait
             #derive_trait
             #impl_trait_macro
        }
-       pub(crate) use #impl_module_name::#impl_make_dyn_trait_macro_name as #impl_make_dyn_trait_macro_name;
+       #recursive_mode_reexports
+       #async_mode_reexports
        pub(crate) use #impl_module_name::#mdt_struct_name as #mdt_struct_name;
-       pub(crate) use #impl_module_name::#visit_field_trait_name as #visit_field_trait_name;
+       pub(crate) use #impl_module_name::#reflect_trait_name as #reflect_trait_name;
+       pub(crate) use #impl_module_name::#field_consts_struct_name as #field_consts_struct_name;
+       pub(crate) use #impl_module_name::#context_struct_name as #context_struct_name;
        pub(crate) use #impl_module_name::#derive_trait_name as #derive_trait_name;
        pub use #impl_module_name::#impl_trait_macro_name as #trait_name;
+       // fails in the crate of the trait, rather than in the ones deriving it, when the trait
+       //  isn't in the `module`
+       const _: () = ::std::assert!(
+           #zambaga_path::is_module(::std::module_path!(), #module_str_literal),
+           #module_message
+       );
     };
 
     // println!("{}", res);
@@ -457,29 +2162,421 @@ fn reflect_impl(_attributes: TokenStream, item: TokenStream) -> TokenStream {
     res
 }
 
+// Rejects the traits whose items can't be forwarded to the derivations.
+fn check_reflected_trait(input: &syn::ItemTrait) -> syn::Result<()> {
+    let mut errors = Vec::new();
+    if !input.generics.params.is_empty() {
+        errors.push(syn::Error::new_spanned(
+            &input.generics,
+            "generic traits are not supported by `#[z::reflect]`",
+        ));
+    }
+    for item in &input.items {
+        match item {
+            syn::TraitItem::Const(item) if !item.generics.params.is_empty() => {
+                errors.push(syn::Error::new_spanned(
+                    &item.generics,
+                    "generic associated consts are not supported by `#[z::reflect]`",
+                ));
+            }
+            syn::TraitItem::Const(_) | syn::TraitItem::Fn(_) | syn::TraitItem::Type(_) => {}
+            syn::TraitItem::Macro(item) => errors.push(syn::Error::new_spanned(
+                item,
+                "macro invocations are not supported in traits reflected with `#[z::reflect]`, \
+                 as their items can't be forwarded to the derivation",
+            )),
+            other => errors.push(syn::Error::new_spanned(
+                other,
+                "unsupported trait item, `#[z::reflect]` supports associated consts, functions \
+                 and types",
+            )),
+        }
+    }
+
+    match errors.into_iter().reduce(|mut errors, error| {
+        errors.combine(error);
+        errors
+    }) {
+        Some(errors) => Err(errors),
+        None => Ok(()),
+    }
+}
+
+// `#[zambaga(key, key = value, ...)]` on a field, as a `&[FieldAnnotation]`
+fn field_annotations(
+    attrs: &[syn::Attribute],
+    zambaga_path: &TokenStream,
+) -> syn::Result<TokenStream> {
+    let mut annotations = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("zambaga")) {
+        attr.parse_nested_meta(|meta| {
+            let key = meta
+                .path
+                .get_ident()
+                .ok_or_else(|| meta.error("expected an annotation name"))?
+                .to_string();
+            let value = if meta.input.peek(syn::Token![=]) {
+                let value = match meta.value()?.parse::<syn::Expr>()? {
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(lit),
+                        ..
+                    }) => lit.value(),
+                    value => value.to_token_stream().to_string(),
+                };
+                quote! { ::std::option::Option::Some(#value) }
+            } else {
+                quote! { ::std::option::Option::None }
+            };
+            annotations
+                .push(quote! { #zambaga_path::FieldAnnotation { key: #key, value: #value } });
+            Ok(())
+        })?;
+    }
+    Ok(quote! { &[#(#annotations),*] })
+}
+
+// `async fn`s as well as the methods returning `impl Future`.
+fn is_async(sig: &syn::Signature) -> bool {
+    fn walk(tokens: TokenStream) -> bool {
+        let mut after_impl = false;
+        for token in tokens {
+            match token {
+                TokenTree::Ident(ident) if ident == "impl" => after_impl = true,
+                TokenTree::Ident(ident) if after_impl && ident == "Future" => return true,
+                TokenTree::Group(group) if walk(group.stream()) => return true,
+                TokenTree::Punct(punct) if matches!(punct.as_char(), ',' | '>' | '=') => {
+                    after_impl = false;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    sig.asyncness.is_some()
+        || match &sig.output {
+            syn::ReturnType::Default => false,
+            syn::ReturnType::Type(_, ty) => walk(ty.to_token_stream()),
+        }
+}
+
+// The methods returning `impl Future<...> + Send`.
+fn returns_send_future(sig: &syn::Signature) -> bool {
+    let bounds = match &sig.output {
+        syn::ReturnType::Type(_, ty) => match &**ty {
+            syn::Type::ImplTrait(ty) => &ty.bounds,
+            _ => return false,
+        },
+        syn::ReturnType::Default => return false,
+    };
+    let has_bound = |name: &str| {
+        bounds.iter().any(|bound| match bound {
+            syn::TypeParamBound::Trait(bound) => {
+                bound.path.segments.last().is_some_and(|segment| segment.ident == name)
+            }
+            _ => false,
+        })
+    };
+    has_bound("Future") && has_bound("Send")
+}
+
+// Replaces the ident with the tokens everywhere in the token stream.
+fn replace_ident(tokens: TokenStream, ident: &str, replacement: &TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(found) if found == ident => replacement.clone(),
+            TokenTree::Group(group) => {
+                let mut replaced = proc_macro2::Group::new(
+                    group.delimiter(),
+                    replace_ident(group.stream(), ident, replacement),
+                );
+                replaced.set_span(group.span());
+                TokenTree::Group(replaced).into()
+            }
+            token => token.into(),
+        })
+        .collect()
+}
+
+// Gives all the tokens the span, wherever they come from.
+fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            if let TokenTree::Group(group) = &token {
+                let mut respanned =
+                    proc_macro2::Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                token = TokenTree::Group(respanned);
+            }
+            token.set_span(span);
+            token
+        })
+        .collect()
+}
+
+// Replaces the named lifetimes of the type with `'_`, so that it can be implemented for without
+//  declaring them.
+fn elide_lifetimes(ty: &syn::Type) -> TokenStream {
+    fn walk(tokens: TokenStream) -> TokenStream {
+        let mut after_apostrophe = false;
+        tokens
+            .into_iter()
+            .map(|token| {
+                let token = match token {
+                    TokenTree::Ident(ident) if after_apostrophe && ident != "static" => {
+                        TokenTree::Ident(Ident::new("_", ident.span()))
+                    }
+                    TokenTree::Group(group) => {
+                        let mut elided =
+                            proc_macro2::Group::new(group.delimiter(), walk(group.stream()));
+                        elided.set_span(group.span());
+                        TokenTree::Group(elided)
+                    }
+                    token => token,
+                };
+                after_apostrophe =
+                    matches!(&token, TokenTree::Punct(punct) if punct.as_char() == '\'');
+                token
+            })
+            .collect()
+    }
+
+    walk(ty.to_token_stream())
+}
+
+// Whether the type mentions `Self` at all, including through its associated items.
+fn mentions_self(ty: &syn::Type) -> bool {
+    fn walk(tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Ident(ident) => ident == "Self",
+            TokenTree::Group(group) => walk(group.stream()),
+            _ => false,
+        })
+    }
+
+    walk(ty.to_token_stream())
+}
+
+// Whether the type mentions `Self` itself, rather than just one of its associated items.
+fn mentions_self_type(output: &syn::ReturnType) -> bool {
+    fn walk(tokens: TokenStream) -> bool {
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Ident(ident) if ident == "Self" => {
+                    let is_path_prefix = matches!(
+                        tokens.peek(),
+                        Some(TokenTree::Punct(punct)) if punct.as_char() == ':'
+                    );
+                    if !is_path_prefix {
+                        return true;
+                    }
+                }
+                TokenTree::Group(group) if walk(group.stream()) => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    match output {
+        syn::ReturnType::Default => false,
+        syn::ReturnType::Type(_, ty) => walk(ty.to_token_stream()),
+    }
+}
+
 #[proc_macro_attribute]
-pub fn derivation(trait_name: PMTokenStream, item: PMTokenStream) -> PMTokenStream {
-    derivation_impl(trait_name.into(), item.into()).into()
+pub fn derivation(args: PMTokenStream, item: PMTokenStream) -> PMTokenStream {
+    derivation_impl(args.into(), item.into()).into()
+}
+
+struct DerivationArgs {
+    trait_name: syn::Path,
+    // extra bounds of the blanket implementation, e.g. on the `FieldTypes` of the derived types
+    where_clause: Option<syn::WhereClause>,
+    zambaga_path: TokenStream,
+}
+
+impl syn::parse::Parse for DerivationArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let trait_name = input.parse()?;
+        let mut zambaga_path = quote! { ::zambaga };
+        let mut where_clause = None;
+        // the `where` clause takes the rest of the arguments
+        while where_clause.is_none()
+            && input.parse::<Option<syn::Token![,]>>()?.is_some()
+            && !input.is_empty()
+        {
+            if input.parse::<Option<syn::Token![crate]>>()?.is_some() {
+                input.parse::<syn::Token![=]>()?;
+                zambaga_path = input.parse::<syn::Path>()?.into_token_stream();
+            } else {
+                where_clause = Some(input.parse()?);
+            }
+        }
+        Ok(DerivationArgs {
+            trait_name,
+            where_clause,
+            zambaga_path,
+        })
+    }
 }
 
-fn derivation_impl(trait_name: TokenStream, item: TokenStream) -> TokenStream {
-    let input: syn::ItemMod = syn::parse2(item.clone()).unwrap();
+fn derivation_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    let DerivationArgs {
+        trait_name,
+        where_clause,
+        zambaga_path,
+    } = match syn::parse2(args) {
+        Ok(args) => args,
+        Err(e) => {
+            let error = e.to_compile_error();
+            return quote! { #item #error };
+        }
+    };
+    let mut input = match syn::parse2::<syn::Item>(item.clone()) {
+        Ok(syn::Item::Mod(input)) if input.content.is_some() => input,
+        Ok(syn::Item::Mod(input)) => {
+            let error = syn::Error::new_spanned(
+                input,
+                "`#[z::derivation]` needs an inline module, e.g. `mod show_derivation { ... }`",
+            )
+            .to_compile_error();
+            return quote! { #item #error };
+        }
+        Ok(other) => {
+            let error =
+                syn::Error::new_spanned(other, "`#[z::derivation]` can only be used on modules")
+                    .to_compile_error();
+            return quote! { #item #error };
+        }
+        Err(e) => {
+            let error = e.to_compile_error();
+            return quote! { #item #error };
+        }
+    };
     let derivation_name = input.ident.clone();
-    let trait_name_snake_case = heck::AsSnakeCase(trait_name.to_string());
 
-    let has_visit_any_method = false; // TODO
-    let validation = if has_visit_any_method {
-        quote! { ::zambaga::Validation::ok() }
+    let predicates = where_clause.map(|where_clause| where_clause.predicates);
+    let items = input.content.as_mut().map(|(_, items)| items).unwrap();
+    // lets the trait tell which of its default items the derivation overrides
+    let provided = items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Const(item) => Some(item.ident.clone()),
+            syn::Item::Fn(item) => Some(item.sig.ident.clone()),
+            syn::Item::Type(item) => Some(item.ident.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let visitor_fn = |name: &str| {
+        items.iter().find_map(|item| match item {
+            syn::Item::Fn(item) if item.sig.ident == name => Some(&item.sig),
+            _ => None,
+        })
+    };
+    // the visitor is whatever the functions take by `&mut` first
+    let visitor_type = |sig: &syn::Signature| match sig.inputs.first() {
+        Some(syn::FnArg::Typed(syn::PatType { ty, .. })) => match &**ty {
+            syn::Type::Reference(syn::TypeReference {
+                mutability: Some(_),
+                elem,
+                ..
+            }) => Ok((**elem).clone()),
+            _ => Err(syn::Error::new_spanned(
+                ty,
+                "expected the field visitor by mutable reference, e.g. `visitor: &mut MyVisitor`",
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            &sig.inputs,
+            "expected the field visitor as the first argument, e.g. `visitor: &mut MyVisitor`",
+        )),
+    };
+    let has_visit_any_method = visitor_fn("visit_any").is_some();
+    let has_validation_fn = visitor_fn("VALIDATION").is_some();
+    let trait_ident = trait_name.segments.last().map(|segment| &segment.ident);
+    // the hook is called with the context, which a `const VALIDATION` couldn't take
+    let validation_const = items.iter().find_map(|item| match item {
+        syn::Item::Const(item) if item.ident == "VALIDATION" => Some(&item.ident),
+        _ => None,
+    });
+    if let Some(ident) = validation_const {
+        let error = syn::Error::new_spanned(
+            ident,
+            format!(
+                "`VALIDATION` has to be a const fn taking the context, e.g. \
+                 `pub const fn VALIDATION<T: Reflect{0}>(context: {0}Context<T>) -> zambaga::Validation`",
+                trait_ident.map(|ident| ident.to_string()).unwrap_or_default(),
+            ),
+        )
+        .to_compile_error();
+        return quote! { #item #error };
+    }
+    let derivation_visitor = match (visitor_fn("visit_implemented"), visitor_fn("visit_any")) {
+        (Some(visit_implemented), visit_any) => {
+            let visitor = visitor_type(visit_implemented).and_then(|visitor| {
+                visit_any.map(visitor_type).transpose()?;
+                Ok(visitor)
+            });
+            match visitor {
+                Ok(visitor) => {
+                    // the lifetimes of the visitor belong to the functions
+                    let visitor = elide_lifetimes(&visitor);
+                    let visit_any = visit_any.map(|_| quote! { self::visit_any });
+                    // the visitor is named from inside the module, so the relative paths of the
+                    //  trait get one more `super`
+                    let first_segment = trait_name.segments.first().map(|segment| &segment.ident);
+                    let trait_path = match first_segment {
+                        _ if trait_name.leading_colon.is_some() => trait_name.to_token_stream(),
+                        Some(ident) if ident == "crate" || ident == "$crate" => {
+                            trait_name.to_token_stream()
+                        }
+                        Some(ident) if ident == "self" => {
+                            let rest = trait_name.segments.iter().skip(1);
+                            quote! { super #(::#rest)* }
+                        }
+                        _ => quote! { super::#trait_name },
+                    };
+                    Some(quote! {
+                        #trait_path!(
+                            @derivation_visitor #visitor; self::visit_implemented; #visit_any
+                        );
+                    })
+                }
+                Err(e) => Some(e.to_compile_error()),
+            }
+        }
+        (None, Some(visit_any)) => Some(
+            syn::Error::new_spanned(
+                &visit_any.ident,
+                "`visit_any` needs a `visit_implemented` function next to it",
+            )
+            .to_compile_error(),
+        ),
+        (None, None) => None,
+    };
+    items.extend(derivation_visitor.map(syn::Item::Verbatim));
+
+    // a `const fn VALIDATION` in the module replaces the default rules
+    let validation = if has_validation_fn {
+        quote! { |context| derivation::VALIDATION(context) }
+    } else if has_visit_any_method {
+        // the fields not implementing the trait are taken care of by `visit_any`
+        quote! { |context| #zambaga_path::Validation::ok() }
     } else {
-        quote! { ::zambaga::Validation::all_fields_impl(&Self::MIRROR) }
+        quote! { |context| #zambaga_path::Validation::all_fields_impl(&context.mirror()) }
     };
 
     let derivation = quote! {
-        #trait_name!(@blanket_impl #derivation_name; #validation);
+        #trait_name!(@blanket_impl #derivation_name; #validation; [#(#provided),*]; #predicates);
     };
 
     quote! {
-        #item
+        #input
         #derivation
     }
 }
@@ -1,10 +1,36 @@
-use enumflags2::bitflags;
+use enumflags2::{bitflags, BitFlags};
+use syn::{ext::IdentExt, parse::ParseStream};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[bitflags]
 #[repr(u8)]
 pub enum ReflectionMode {
+    // static dispatch through the `Visit{Trait}Field` visitors
     Recursive,
     // dynamic dispatch (object safe only)
     Dyn,
-}
\ No newline at end of file
+}
+
+impl ReflectionMode {
+    // parses `recursive`, `dyn` or `recursive | dyn`
+    pub fn parse_flags(input: ParseStream) -> syn::Result<BitFlags<ReflectionMode>> {
+        let mut modes = BitFlags::empty();
+        loop {
+            let ident = syn::Ident::parse_any(input)?;
+            modes |= match ident.to_string().as_str() {
+                "recursive" => ReflectionMode::Recursive,
+                "dyn" => ReflectionMode::Dyn,
+                _ => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        "unknown reflection mode, expected `recursive` or `dyn`",
+                    ))
+                }
+            };
+            if !input.peek(syn::Token![|]) {
+                return Ok(modes);
+            }
+            input.parse::<syn::Token![|]>()?;
+        }
+    }
+}
@@ -7,21 +7,28 @@ pub trait Show {
 }
 
 // `#[z::reflect]` generates a few items for you:
-// 1. Trait called `Derive${TraitName}`, in this example `DeriveShow`. You need to provide a 
-//  blanket implementation of this trait.
-// 2. Trait called `Visit${TraitName}Field`, in this example `VisitShowField`. You need to 
-//  provide an implementation of this trait for a concrete visitor type.
-// 3. Macro called `${trait_name}_field_visitor`, which you need to invoke passing the visitor 
-//  type into it.
+// 1. Trait called `Derive${TraitName}`, in this example `DeriveShow`. You need to provide a
+//  blanket implementation of this trait for the types implementing `Reflect${TraitName}`, which
+//  `#[z::derive]` implements for you.
+// 2. Trait called `Visit${TraitName}Field`, in this example `VisitShowField`. You need to
+//  implement it for your visitor types, as many of them as the derivation needs.
+// Item 2. comes from the `recursive` reflection mode. The `dyn` mode provides the
+//  `Option<&dyn Show>`s returned from `fields()`. Both modes are on by default; traits which
+//  aren't object safe can be reflected with `#[z::reflect(mode = recursive)]`.
+// The crates deriving the trait reach these items through the root of this crate. A trait defined
+//  in a submodule needs to say which, e.g. `#[z::reflect(module = crate::traits)]`.
+// When zambaga is only available through another crate, `crate = other::zambaga` tells
+//  `#[z::reflect]`, `#[z::derivation]` and `#[z::derive]` where to find it.
 impl<T> DeriveShow for T
 where
-    T: zambaga::WithMirror<MDTShow>,
+    T: ReflectShow,
 {
-    // The VALIDATION constant is used to check if the type conforms to the requirements of the 
+    // The VALIDATION constant is used to check if the type conforms to the requirements of the
     //  derivation.
     const VALIDATION: zambaga::Validation = zambaga::Validation::ok();
     // The default behavior is to reject the structs with fields that don't recursively implement
-    //  the trait. Try commenting out the line above.
+    //  the trait. Try commenting out the line above. The rules can be combined with `and`/`or`,
+    //  e.g. `Validation::require_struct(&Self::MIRROR).and(Validation::max_fields(&Self::MIRROR, 8))`.
     // const VALIDATION: zambaga::Validation = zambaga::Validation::all_fields_impl(&Self::MIRROR);
 
     fn print(&self, indentation: usize) -> String {
@@ -32,18 +39,17 @@ where
         writeln!(&mut output, "{} {{", mirror.name.runtime()).unwrap();
 
         // You can visit the fields generically using the `visit_fields` method.
-        //  The types of the fields are provided to the visitor as actual concrete types plugged 
-        //  into the generic parameters.
-        let DeriveShowVisitor { mut output, .. } = self.visit_fields(DeriveShowVisitor {
-            output,
+        //  The types of the fields are provided to the visitor as actual concrete types plugged
+        //  into the generic parameters. The visitor is handed back, unless you lend it with `&mut`.
+        self.visit_fields(&mut DeriveShowVisitor {
+            output: &mut output,
             indentation: indentation + 2,
-            index: 1,
         });
 
         write!(&mut output, "{:indentation$}}}", "").unwrap();
 
-        // You can also iterate over the fields and get `Option<&dyn TraitName>` for each field 
-        //  if the trait is object safe. The option will be empty if the field type does not 
+        // You can also iterate over the fields and get `Option<&dyn TraitName>` for each field
+        //  if the trait is object safe. The option will be empty if the field type does not
         //  implement the trait.
         for (i, (field_name, field_type, recursive_instance)) in self.fields().enumerate() {
             eprintln!(
@@ -56,51 +62,67 @@ where
     }
 }
 
-pub struct DeriveShowVisitor {
-    output: String,
+pub struct DeriveShowVisitor<'o> {
+    output: &'o mut String,
     indentation: usize,
-    index: usize,
 }
 
-show_field_visitor!(DeriveShowVisitor);
+// The `FieldContext` tells the visitors where the field is: its name and type, its position among
+//  the fields of its struct (or enum variant) and the `#[zambaga(...)]` annotations on it.
 
-impl VisitShowField for DeriveShowVisitor {
-    fn visit_implemented<T: Show>(
-        &mut self,
-        field_value: &T,
-        field_name: Option<zambaga::FieldName>,
-        _field_type: zambaga::TypeName,
-    ) {
+impl VisitShowField for DeriveShowVisitor<'_> {
+    fn visit_implemented<T: Show>(&mut self, field_value: &T, field: zambaga::FieldContext) {
         writeln!(
             self.output,
             "{:indentation$}field #{index} {name}: {}",
             "",
             field_value.print(self.indentation),
             indentation = self.indentation,
-            name = field_name.map(|x| x.0).unwrap_or("?"),
-            index = self.index
+            name = field.name.map(|x| x.0).unwrap_or("?"),
+            index = field.index + 1
         )
         .unwrap();
-        self.index += 1;
     }
 
-    fn visit_any(
-        &mut self,
-        _field_value: &dyn Any,
-        field_name: Option<zambaga::FieldName>,
-        field_type: zambaga::TypeName,
-    ) {
+    fn visit_any(&mut self, _field_value: &dyn Any, field: zambaga::FieldContext) {
         writeln!(
             self.output,
             "{:indentation$}field #{index} {name}: <value of type `{typ}` which does not implement Show>",
             "",
             indentation = self.indentation,
-            name = field_name.map(|x| x.0).unwrap_or("?"),
-            typ = field_type.runtime(),
-            index = self.index
+            name = field.name.map(|x| x.0).unwrap_or("?"),
+            typ = field.type_name.runtime(),
+            index = field.index + 1
         )
         .unwrap();
-        self.index += 1;
+    }
+}
+
+// Reducing the fields to a single value doesn't need a visitor of its own, a folder does. Here the
+//  number of fields which can be shown, and of those which can't.
+pub fn count_showable<T: DeriveShow>(value: &T) -> (usize, usize) {
+    value.fold_fields((0, 0), ShowableCounter)
+}
+
+struct ShowableCounter;
+
+impl FoldShowField<(usize, usize)> for ShowableCounter {
+    fn fold_implemented<T: Show>(
+        &mut self,
+        (showable, other): (usize, usize),
+        _field_value: &T,
+        _field: zambaga::FieldContext,
+    ) -> (usize, usize) {
+        (showable + 1, other)
+    }
+
+    fn fold_any(
+        &mut self,
+        (showable, other): (usize, usize),
+        _field_value: &dyn Any,
+        _field: zambaga::FieldContext,
+    ) -> (usize, usize) {
+        (showable, other + 1)
     }
 }
 
@@ -109,3 +131,288 @@ impl Show for String {
         self.clone()
     }
 }
+
+// Methods without `self`, as well as the ones returning `Self`, can be derived too. The type-level
+//  counterparts of the visitors, `Visit${TraitName}FieldType` and `Build${TraitName}Field`, give
+//  access to the field types and let you build a value of the derived type out of the fields.
+//  `Map${TraitName}Field` builds it out of the fields of `self` instead.
+#[z::reflect(mode = recursive)]
+pub trait Example {
+    fn example() -> Self;
+    fn describe() -> String;
+    fn doubled(&self) -> Self;
+
+    // Default methods are kept for the derived types, unless the derivation module provides an
+    //  item with the same name. A hand written `DeriveExample` implementation can override them
+    //  the same way as the other items.
+    fn greet() -> String {
+        format!("Hello from {}", Self::describe())
+    }
+}
+
+// In a `#[z::derivation]` module the functions for such methods take an `${TraitName}Context`,
+//  followed by `self` for the methods which have one.
+#[z::derivation(Example)]
+pub mod example_derivation {
+    use super::*;
+
+    pub fn example<T: ReflectExample>(context: ExampleContext<T>) -> T {
+        match context.build_fields(ExampleBuilder) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    pub fn describe<T: ReflectExample>(context: ExampleContext<T>) -> String {
+        let DescribeVisitor { fields } =
+            context.visit_field_types(DescribeVisitor { fields: vec![] });
+        format!(
+            "{} {{ {} }}",
+            context.mirror().name.compiletime(),
+            fields.join(", ")
+        )
+    }
+
+    pub fn doubled<T: ReflectExample>(_context: ExampleContext<T>, zelf: &T) -> T {
+        match zelf.map_fields(DoubleMapper) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+}
+
+pub struct ExampleBuilder;
+
+impl BuildExampleField for ExampleBuilder {
+    type Error = std::convert::Infallible;
+
+    fn build_implemented<T: Example>(
+        &mut self,
+        _field: zambaga::FieldContext,
+    ) -> Result<T, Self::Error> {
+        Ok(T::example())
+    }
+}
+
+pub struct DoubleMapper;
+
+impl MapExampleField for DoubleMapper {
+    type Error = std::convert::Infallible;
+
+    fn map_implemented<T: Example>(
+        &mut self,
+        field_value: &T,
+        _field: zambaga::FieldContext,
+    ) -> Result<T, Self::Error> {
+        Ok(field_value.doubled())
+    }
+}
+
+pub struct DescribeVisitor {
+    fields: Vec<String>,
+}
+
+impl VisitExampleFieldType for DescribeVisitor {
+    fn visit_implemented<T: Example>(&mut self, field: zambaga::FieldContext) {
+        let description = T::describe();
+        self.fields.push(match field.name {
+            Some(field_name) => format!("{}: {description}", field_name.0),
+            None => description,
+        });
+    }
+}
+
+// Associated types are forwarded to type aliases in the derivation module, which get the
+//  implementing type as their first type parameter. `WithMirror::FieldTypes` holds the field types
+//  as a tuple, so the alias can compute the type from them. The blanket implementation needs to
+//  know that it can do so, which is what the `where` clause passed to `#[z::derivation]` is for.
+// Associated consts are forwarded to const fns taking the context. The mirror exposes the consts
+//  of every field (`None` for fields not implementing the trait), so they can be folded over.
+#[z::reflect(mode = recursive)]
+pub trait Skeleton {
+    type Skeleton: std::fmt::Debug + Default;
+    const LEAVES: usize;
+}
+
+#[z::derivation(Skeleton, where Self::FieldTypes: SkeletonFields)]
+pub mod skeleton_derivation {
+    use super::*;
+
+    pub type Skeleton<T> =
+        <<T as zambaga::WithMirror<MDTSkeleton>>::FieldTypes as SkeletonFields>::Skeleton;
+
+    #[allow(non_snake_case)]
+    pub const fn LEAVES<T: ReflectSkeleton>(context: SkeletonContext<T>) -> usize {
+        let mirror = context.mirror();
+        let mut leaves = 0;
+        let mut i = 0;
+        while i < mirror.field_count() {
+            if let Some(field_leaves) = mirror.field_impl(i).consts.LEAVES {
+                leaves += field_leaves;
+            }
+            i += 1;
+        }
+        leaves
+    }
+
+    // A `VALIDATION` const fn replaces the default rules of the derivation. The field types of an
+    //  enum are those of all its variants together, which doesn't make for a meaningful skeleton.
+    #[allow(non_snake_case)]
+    pub const fn VALIDATION<T: ReflectSkeleton>(
+        context: SkeletonContext<T>,
+    ) -> zambaga::Validation {
+        let mirror = context.mirror();
+        zambaga::Validation::all_fields_impl(&mirror).and(
+            zambaga::Validation::require_struct(&mirror)
+                .with_message("skeletons of enums would mix the fields of all their variants"),
+        )
+    }
+}
+
+pub trait SkeletonFields {
+    type Skeleton: std::fmt::Debug + Default;
+}
+
+macro_rules! impl_skeleton_fields {
+    ($($field:ident),*) => {
+        impl<$($field: Skeleton),*> SkeletonFields for ($($field,)*) {
+            type Skeleton = ($($field::Skeleton,)*);
+        }
+    };
+}
+
+impl_skeleton_fields!();
+impl_skeleton_fields!(A);
+impl_skeleton_fields!(A, B);
+impl_skeleton_fields!(A, B, C);
+
+impl Skeleton for String {
+    type Skeleton = &'static str;
+    const LEAVES: usize = 1;
+}
+
+impl Skeleton for u64 {
+    type Skeleton = u64;
+    const LEAVES: usize = 1;
+}
+
+// Async methods, as well as the ones returning `impl Future`, get `Visit${TraitName}FieldAsync`
+//  and `visit_field_values_async`, which awaits the visitor on each field in turn. When a method
+//  returns `impl Future<Output = ...> + Send`, the futures of the visitors are `Send` too, and the
+//  derivation needs `where Self: Sync` for the ones borrowing `self`.
+#[allow(async_fn_in_trait)]
+#[z::reflect(mode = recursive)]
+pub trait Collect {
+    async fn collect(&self, sink: &mut Vec<String>);
+}
+
+#[z::derivation(Collect)]
+pub mod collect_derivation {
+    use super::*;
+
+    pub async fn collect<T: ReflectCollect>(zelf: &T, sink: &mut Vec<String>) {
+        zelf.visit_field_values_async(CollectVisitor { sink }).await;
+    }
+
+    struct CollectVisitor<'s> {
+        sink: &'s mut Vec<String>,
+    }
+
+    impl VisitCollectFieldAsync for CollectVisitor<'_> {
+        async fn visit_implemented<T: Collect>(
+            &mut self,
+            field_value: &T,
+            _field: zambaga::FieldContext,
+        ) {
+            field_value.collect(self.sink).await;
+        }
+    }
+}
+
+// Zipping visits the matching fields of two values together, which is what comparisons need.
+#[z::reflect(mode = recursive)]
+pub trait Same {
+    fn same(&self, other: &Self) -> bool;
+}
+
+#[z::derivation(Same)]
+pub mod same_derivation {
+    use super::*;
+
+    pub fn same<T: ReflectSame>(zelf: &T, other: &T) -> bool {
+        zelf.zip_field_values(other, SameVisitor(true)).0
+    }
+
+    struct SameVisitor(bool);
+
+    impl VisitSameFieldPair for SameVisitor {
+        fn visit_implemented<T: Same>(
+            &mut self,
+            field_value: &T,
+            other_field_value: &T,
+            _field: zambaga::FieldContext,
+        ) {
+            self.0 = self.0 && field_value.same(other_field_value);
+        }
+
+        fn visit_different_variants(
+            &mut self,
+            _variant: zambaga::VariantName,
+            _other_variant: zambaga::VariantName,
+        ) {
+            self.0 = false;
+        }
+    }
+}
+
+impl Same for String {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Same for u64 {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl Collect for String {
+    async fn collect(&self, sink: &mut Vec<String>) {
+        sink.push(self.clone());
+    }
+}
+
+impl Collect for u64 {
+    async fn collect(&self, sink: &mut Vec<String>) {
+        sink.push(self.to_string());
+    }
+}
+
+impl Example for String {
+    fn example() -> Self {
+        "example".to_string()
+    }
+
+    fn describe() -> String {
+        "String".to_string()
+    }
+
+    fn doubled(&self) -> Self {
+        self.repeat(2)
+    }
+}
+
+impl Example for u64 {
+    fn example() -> Self {
+        42
+    }
+
+    fn describe() -> String {
+        "u64".to_string()
+    }
+
+    fn doubled(&self) -> Self {
+        self * 2
+    }
+}
@@ -1,15 +1,44 @@
-use trait_def::{Show, __zambaga_show_impl}; // unfortunately we need to import the extra impl module
+use trait_def::{Example, Same, Show, Skeleton};
 use zambaga::macros as z;
+use zerde::Zerde;
 
 #[z::derive(Show)]
+#[z::derive(Example)]
+#[z::derive(Skeleton)]
+#[z::derive(Zerde)]
+// The traits don't need to be imported, they can be derived by their path too.
+#[z::derive(trait_def::Collect)]
+#[z::derive(Same)]
 struct Foo(String);
 
 #[z::derive(Show)]
+#[z::derive(Example)]
+#[z::derive(Skeleton)]
+// The validation of the derivation can be replaced for a single type, here to make sure that its
+//  JSON keys stay readable. `validation = unchecked` turns the checks off altogether, leaving the
+//  fields which don't implement the trait to the `visit_any` method of the visitors.
+#[z::derive(Zerde, validation = |mirror| zambaga::Validation::all_fields_impl(&mirror)
+    .and(zambaga::Validation::require_named_fields(&mirror)))]
+#[z::derive(trait_def::Collect)]
+#[z::derive(Same)]
 struct Bar {
     foo: Foo,
+    // annotations are handed to the visitors of every derived trait
+    #[zambaga(rename = "somethingElse")]
     something_else: u64,
 }
 
+// Enums visit the fields of the variant they hold. Values are built as the first variant.
+// The traits can also be derived with a regular derive macro, which leaves the item as it is. The
+//  arguments of `#[z::derive]` go in parentheses after the trait, e.g. `Zerde(validation = unchecked)`.
+#[derive(zambaga::Reflect)]
+#[zambaga(derive(Show, Example, Zerde, trait_def::Collect, Same))]
+enum Baz {
+    Bar { bar: Bar },
+    Number(u64),
+    Nothing,
+}
+
 fn main() {
     println!(
         "{}",
@@ -21,4 +50,59 @@ fn main() {
             0
         )
     );
+
+    println!("{}", Bar::describe());
+    println!("{}", Bar::greet());
+    println!("{}", Show::print(&Bar::example(), 0));
+    println!("{}", Show::print(&Bar::example().doubled(), 0));
+    println!("{:?}", <Bar as Skeleton>::Skeleton::default());
+    println!("{}", <Bar as Skeleton>::LEAVES);
+
+    let bar = Bar {
+        foo: Foo("zerde".to_string()),
+        something_else: 7,
+    };
+    let mut json = Vec::new();
+    Zerde::to_json(&bar, &mut json).unwrap();
+    println!("{}", String::from_utf8(json).unwrap());
+    let mut encoded = Vec::new();
+    Zerde::encode(&bar, &mut encoded).unwrap();
+    println!("{encoded:?}");
+
+    println!("{:?}", trait_def::count_showable(&bar));
+
+    let mut collected = Vec::new();
+    block_on(trait_def::Collect::collect(&bar, &mut collected));
+    println!("{collected:?}");
+
+    for value in [Baz::example(), Baz::Number(3), Baz::Nothing] {
+        println!("{}", Show::print(&value, 0));
+        let mut json = Vec::new();
+        Zerde::to_json(&value, &mut json).unwrap();
+        println!("{}", String::from_utf8(json).unwrap());
+    }
+    println!("{}", Baz::describe());
+    let mut json = Vec::new();
+    Zerde::to_json(&Baz::Number(3).doubled(), &mut json).unwrap();
+    println!("{}", String::from_utf8(json).unwrap());
+
+    for (value, other) in [
+        (Baz::Number(3), Baz::Number(3)),
+        (Baz::Number(3), Baz::Number(4)),
+        (Baz::Number(3), Baz::Nothing),
+        (Baz::example(), Baz::example()),
+    ] {
+        println!("{}", Same::same(&value, &other));
+    }
+}
+
+// the futures in this example never wait on anything, so there is no need for a real executor
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
 }
@@ -1,29 +1,106 @@
+use std::{
+    io::{self, Write},
+    ops::ControlFlow,
+};
 use zambaga::macros as z;
 
-#[z::reflect]
+// `impl Trait` arguments make the trait unusable as `dyn Zerde`, hence only the recursive mode.
+// The signatures end up in the crates deriving the trait, so they spell out full paths.
+#[z::reflect(mode = recursive)]
 pub trait Zerde {
-    // TODO: support `output: impl std::io::Write`
-    fn to_json(&self, output: &mut dyn std::io::Write) -> std::io::Result<()>;
+    fn to_json(&self, output: impl std::io::Write) -> std::io::Result<()>;
+    fn encode<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()>;
 }
 
-pub struct NoOpVisitor;
-
-zerde_field_visitor!(NoOpVisitor);
-
+// Generic methods are forwarded together with their type parameters, which the derivation
+//  functions get after the implementing type. `visit_fields` and `visit_field_values` accept any
+//  visitor, so every method can use its own one, generic over the parameters if need be.
+// `visit_implemented` (and `visit_any`, if there is one) make a field visitor out of the type they
+//  take as their first argument. Without `visit_any` the derived types need all their fields to
+//  implement the trait.
 #[z::derivation(Zerde)]
-pub mod ZerdeDerivation {
+pub mod zerde_derivation {
     use super::*;
 
-    pub fn to_json<S>(zelf: &S, output: &mut dyn std::io::Write) -> std::io::Result<()> {
-        output.write_all(b"{}")
+    pub fn to_json<S: DeriveZerde>(zelf: &S, mut output: impl Write) -> io::Result<()> {
+        output.write_all(b"{")?;
+        zelf.visit_fields(JsonFields {
+            output: &mut output,
+            result: Ok(()),
+        })
+        .result?;
+        output.write_all(b"}")
+    }
+
+    // the first error stops the visitation and gets returned from it
+    pub fn encode<S: ReflectZerde, W: Write>(zelf: &S, output: &mut W) -> io::Result<()> {
+        zelf.try_visit_field_values_result(&mut EncodeFieldVisitor { output })
+    }
+
+    // visitors can borrow, here the output they write into
+    pub struct JsonFields<'w> {
+        output: &'w mut dyn Write,
+        result: io::Result<()>,
     }
 
-    pub fn visit_implemented<T: Zerde>(
-        output: &mut dyn std::io::Write,
+    pub fn visit_implemented<'w, T: Zerde>(
+        visitor: &mut JsonFields<'w>,
         field_value: &T,
-        field_name: Option<zambaga::FieldName>,
-        _field_type: zambaga::TypeName,
+        field: zambaga::FieldContext,
     ) {
-        field_value.to_json(output).unwrap();
+        if visitor.result.is_ok() {
+            let separator = if field.is_first() { "" } else { "," };
+            // `#[zambaga(rename = "key")]` picks the key, tuple struct fields are keyed by their index
+            visitor.result = match (field.annotation("rename"), field.name) {
+                (
+                    Some(zambaga::FieldAnnotation {
+                        value: Some(key), ..
+                    }),
+                    _,
+                ) => {
+                    write!(visitor.output, "{separator}\"{key}\":")
+                }
+                (_, Some(name)) => write!(visitor.output, "{separator}\"{}\":", name.0),
+                (_, None) => write!(visitor.output, "{separator}\"{}\":", field.index),
+            }
+            .and_then(|()| field_value.to_json(&mut *visitor.output));
+        }
+    }
+}
+
+struct EncodeFieldVisitor<'w, W> {
+    output: &'w mut W,
+}
+
+impl<W: Write> TryVisitZerdeField for EncodeFieldVisitor<'_, W> {
+    type Break = io::Error;
+
+    fn try_visit_implemented<T: Zerde>(
+        &mut self,
+        field_value: &T,
+        _field: zambaga::FieldContext,
+    ) -> ControlFlow<io::Error> {
+        zambaga::break_on_err(field_value.encode(self.output))
+    }
+}
+
+impl Zerde for u64 {
+    fn to_json(&self, mut output: impl Write) -> io::Result<()> {
+        write!(output, "{self}")
+    }
+
+    fn encode<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Zerde for String {
+    fn to_json(&self, mut output: impl Write) -> io::Result<()> {
+        write!(output, "{self:?}")
+    }
+
+    fn encode<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        (self.len() as u64).encode(output)?;
+        output.write_all(self.as_bytes())
     }
 }
@@ -0,0 +1,42 @@
+use std::io::{self, Write};
+use zambaga::macros as z;
+use zerde::Zerde;
+
+#[z::derive(Zerde)]
+struct Triple(u64, u64, u64);
+
+// fails the writes which don't fit, counting them
+struct Limited {
+    written: Vec<u8>,
+    capacity: usize,
+    rejected: usize,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written.len() + buf.len() > self.capacity {
+            self.rejected += 1;
+            return Err(io::Error::other("full"));
+        }
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn encode_stops_at_the_first_error() {
+    let mut output = Limited {
+        written: vec![],
+        capacity: 8,
+        rejected: 0,
+    };
+    let error = Triple(1, 2, 3).encode(&mut output).unwrap_err();
+    assert_eq!(error.to_string(), "full");
+    assert_eq!(output.written, 1u64.to_le_bytes());
+    // the third field isn't written at all
+    assert_eq!(output.rejected, 1);
+}